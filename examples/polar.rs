@@ -0,0 +1,44 @@
+use bevy::{prelude::*, render::view::Hdr};
+use bevy_infinite_grid::{GridKind, InfiniteGridBundle, InfiniteGridPlugin, InfiniteGridSettings};
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, InfiniteGridPlugin))
+        .add_systems(Startup, setup_system)
+        .run();
+}
+
+fn setup_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(InfiniteGridBundle {
+        settings: InfiniteGridSettings {
+            kind: GridKind::Polar {
+                ring_spacing: 1.,
+                spoke_count: 24,
+            },
+            major_line_every: 6,
+            ..default()
+        },
+        ..default()
+    });
+
+    commands.spawn((
+        Camera3d::default(),
+        Hdr,
+        Transform::from_xyz(0.0, 12.0, 14.77).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+
+    commands.spawn((
+        DirectionalLight { ..default() },
+        Transform::from_translation(Vec3::X * 15. + Vec3::Y * 20.).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+
+    commands.spawn((
+        Mesh3d(meshes.add(Cylinder::new(0.5, 1.0))),
+        MeshMaterial3d(standard_materials.add(StandardMaterial::default())),
+        Transform::from_xyz(0.0, 0.5, 0.0),
+    ));
+}
@@ -1,19 +1,108 @@
+#ifdef GRID_SHADOWS
+#import bevy_pbr::{
+    clustered_forward as clustering,
+    mesh_view_bindings as view_bindings,
+    mesh_view_types,
+    shadows,
+}
+#endif
+
+// Positions are relative to the camera so that they stay small wherever the camera is
 struct InfiniteGridPosition {
     planar_rotation_matrix: mat3x3<f32>,
     origin: vec3<f32>,
     normal: vec3<f32>,
-
+    // Camera position in cells, reduced modulo the period after which the lines repeat
+    cell_phase: vec2<f32>,
+    // Same for the lines further apart than cell_period, reduced modulo a larger period
+    coarse_cell_phase: vec2<f32>,
+    cell_period: f32,
+    // Range of the grid's clip polygon in clip_polygons, empty when it has none
+    clip_polygon_start: u32,
+    clip_polygon_len: u32,
+    // Maps plane coordinates relative to overlay_origin to the UVs of the overlay image
+    overlay_from_plane: mat2x2<f32>,
+    // Overlay origin relative to the camera's projection on the plane
+    overlay_origin: vec2<f32>,
+    // 1 when the grid has an overlay whose image is loaded
+    overlay: u32,
+    // Added to the cells of the fragments to get their actual coordinates
+    cell_shift: vec2<i32>,
+    // Cell of the heatmap image's first texel
+    heatmap_origin: vec2<i32>,
+    heatmap_range: vec2<f32>,
+    // Range of the heatmap's colors in heatmap_ramps, empty when it has none
+    heatmap_ramp_start: u32,
+    heatmap_ramp_len: u32,
+    // Range of the grid's highlights in cell_highlights, empty when it has none
+    cell_highlight_start: u32,
+    cell_highlight_len: u32,
+    cell_highlight_outline_width: f32,
+    // Intersection the cursor snapped to, in the same cells as the fragments
+    cursor_coord: vec2<f32>,
+    // 1 when the grid has a cursor
+    cursor: u32,
 };
 
 struct InfiniteGridSettings {
-    scale: f32,
-    // 1 / fadeout_distance
-    dist_fadeout_const: f32,
-    dot_fadeout_const: f32,
+    // scale / cell_size
+    scale: vec2<f32>,
+    // Matches GridFade, along the distance to the camera and one minus the cosine of the angle to
+    // the grid's normal
+    fade_kind: u32,
+    grazing_fade_kind: u32,
+    // Start and end, or density in x
+    fade_params: vec2<f32>,
+    grazing_fade_params: vec2<f32>,
+    // 0 when the subdivision is fixed
+    lod_base: f32,
+    major_line_every: f32,
+    // 0 when there is no super-major tier
+    super_major_line_every: f32,
+    // 0 when line widths are in pixels, 1 when they are in world units
+    line_width_mode: u32,
+    minor_line_width: f32,
+    major_line_width: f32,
+    super_major_line_width: f32,
+    axis_line_width: f32,
+    minor_line_style: u32,
+    major_line_style: u32,
+    polar_ring_spacing: f32,
+    polar_spoke_count: f32,
+    // 1 when hexagons have a flat side along x instead of a vertex
+    hex_flat_top: u32,
+    // 1 / y_axis_fadeout_distance
+    y_axis_dist_fadeout_const: f32,
+    // Alpha below which masked grids are discarded
+    alpha_mask_cutoff: f32,
+    // 0 when unbounded, 1 for a rectangle and 2 for a circle
+    bounds_kind: u32,
+    // Half size of the rectangle, or radius of the circle in x
+    bounds_half_size: vec2<f32>,
+    bounds_falloff: f32,
+    border_line_width: f32,
+    // Share of their color the lines lose in full shadow
+    shadow_darkening: f32,
+    // 1 to fade into the view's fog instead of over the fadeout distance
+    distance_fog: u32,
+    fill_fade_kind: u32,
+    fill_fade_params: vec2<f32>,
+    // 0 when there is no crosshair or circle
+    cursor_crosshair_length: f32,
+    cursor_circle_radius: f32,
+    cursor_line_width: f32,
     x_axis_col: vec3<f32>,
+    y_axis_col: vec3<f32>,
     z_axis_col: vec3<f32>,
     minor_line_col: vec4<f32>,
     major_line_col: vec4<f32>,
+    super_major_line_col: vec4<f32>,
+    border_col: vec4<f32>,
+    shadow_col: vec4<f32>,
+    // Both are the same for solid fills
+    fill_col: vec4<f32>,
+    fill_checker_col: vec4<f32>,
+    cursor_col: vec4<f32>,
 
 };
 
@@ -22,21 +111,84 @@ struct View {
     inverse_projection: mat4x4<f32>,
     view: mat4x4<f32>,
     inverse_view: mat4x4<f32>,
-    world_position: vec3<f32>,
+    // Last frame's clip_from_world, for positions relative to the camera's current position
+    previous_projection: mat4x4<f32>,
+    // The camera's DistanceFog, laid out like Bevy's own fog uniform
+    fog_color: vec4<f32>,
+    fog_be: vec3<f32>,
+    fog_mode: u32,
+    fog_bi: vec3<f32>,
+};
+
+// Sorted by row and then column within each grid
+struct CellHighlight {
+    cell: vec2<i32>,
+    fill_col: vec4<f32>,
+    outline_col: vec4<f32>,
 };
 
-@group(0) @binding(0) var<uniform> view: View;
+// Grids that receive shadows come after Bevy's mesh view bind groups
+@group(#{GRID_VIEW_BIND_GROUP}) @binding(0) var<uniform> view: View;
+
+@group(#{GRID_BIND_GROUP}) @binding(0) var<uniform> grid_position: InfiniteGridPosition;
+@group(#{GRID_BIND_GROUP}) @binding(1) var<uniform> grid_settings: InfiniteGridSettings;
+// Fallback images when the grid has no overlay, or its heatmap's image isn't loaded
+@group(#{GRID_BIND_GROUP}) @binding(2) var overlay_texture: texture_2d<f32>;
+@group(#{GRID_BIND_GROUP}) @binding(3) var overlay_sampler: sampler;
+#ifdef GRID_HEATMAP
+@group(#{GRID_BIND_GROUP}) @binding(4) var heatmap_texture: texture_2d<f32>;
+#endif
+#ifdef GRID_CLIP_POLYGON
+@group(#{GRID_BIND_GROUP}) @binding(5) var<storage> clip_polygons: array<vec2<f32>>;
+#endif
+#ifdef GRID_HEATMAP
+@group(#{GRID_BIND_GROUP}) @binding(6) var<storage> heatmap_ramps: array<vec4<f32>>;
+#endif
+#ifdef GRID_CELL_HIGHLIGHTS
+@group(#{GRID_BIND_GROUP}) @binding(7) var<storage> cell_highlights: array<CellHighlight>;
+#endif
+
+const TAU: f32 = 6.283185307179586;
+const SQRT_3: f32 = 1.7320508075688772;
+
+// Finest spacing, in pixels, that adaptive subdivision lets lines get to before fading them out
+const LOD_MIN_CELL_PIXELS: f32 = 8.0;
+
+// Matches GridLineStyle
+const LINE_STYLE_SOLID: u32 = 0u;
+const LINE_STYLE_DOTTED: u32 = 1u;
+const LINE_STYLE_CROSSHAIR: u32 = 2u;
+const LINE_STYLE_DASHED: u32 = 3u;
+// Length of each arm of a crosshair, in cells
+const CROSSHAIR_ARM_LENGTH: f32 = 0.2;
+const DASHES_PER_CELL: f32 = 4.0;
+
+// Matches GridBounds
+const BOUNDS_NONE: u32 = 0u;
+const BOUNDS_CIRCLE: u32 = 2u;
 
-@group(1) @binding(0) var<uniform> grid_position: InfiniteGridPosition;
-@group(1) @binding(1) var<uniform> grid_settings: InfiniteGridSettings;
+// Matches GridFade
+const FADE_NONE: u32 = 0u;
+const FADE_LINEAR: u32 = 1u;
+const FADE_EXPONENTIAL: u32 = 2u;
+const FADE_EXPONENTIAL_SQUARED: u32 = 3u;
+const FADE_SMOOTHSTEP: u32 = 4u;
+
+// Matches Bevy's fog modes
+const FOG_MODE_OFF: u32 = 0u;
+const FOG_MODE_LINEAR: u32 = 1u;
+const FOG_MODE_EXPONENTIAL: u32 = 2u;
+const FOG_MODE_EXPONENTIAL_SQUARED: u32 = 3u;
+const FOG_MODE_ATMOSPHERIC: u32 = 4u;
 
 struct Vertex {
     @builtin(vertex_index) index: u32,
 };
 
+// Unprojects to a position relative to the camera, leaving its translation out to keep precision
 fn unproject_point(p: vec3<f32>) -> vec3<f32> {
-    let unprojected = view.view * view.inverse_projection * vec4<f32>(p, 1.0);
-    return unprojected.xyz / unprojected.w;
+    let unprojected = view.inverse_projection * vec4<f32>(p, 1.0);
+    return (view.view * vec4(unprojected.xyz / unprojected.w, 0.0)).xyz;
 }
 
 struct VertexOutput {
@@ -64,8 +216,327 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     return out;
 }
 
+// Per-axis width in pixels of a line of the given width, which is either in pixels or in world units
+// depending on the line width mode
+fn line_width_pixels(width: f32, coord_per_world: vec2<f32>, derivative: vec2<f32>) -> vec2<f32> {
+    if grid_settings.line_width_mode == 1u {
+        return width * coord_per_world / derivative;
+    }
+    return vec2(width);
+}
+
+// Antialiased coverage of a line `width` pixels wide, given the per-axis distance to it in pixels.
+// Lines thinner than a pixel are drawn one pixel wide and faded instead, which avoids aliasing.
+fn line_alpha(dist: vec2<f32>, width: vec2<f32>) -> vec2<f32> {
+    return saturate(max(width, vec2(1.0)) * 0.5 + 0.5 - dist) * saturate(width);
+}
+
+// Antialiased coverage of the lines `spacing` cells apart, given the screen-space derivative of `coord`
+fn line_coverage(coord: vec2<f32>, derivative: vec2<f32>, spacing: f32, width: vec2<f32>, style: u32) -> f32 {
+    let cell_pixels = spacing / derivative;
+    let dist = abs(fract(coord / spacing - 0.5) - 0.5) * cell_pixels;
+    let lines = line_alpha(dist, width);
+    // Once the cells shrink towards a pixel, blend towards the share of the cell covered by the line
+    // so that distant lines turn into an even tint instead of moire
+    let share = saturate(width / cell_pixels);
+    let blend = saturate(2.0 / cell_pixels - 1.0);
+
+    switch style {
+        case LINE_STYLE_DOTTED: {
+            let radius = max(max(width.x, width.y), 1.0) * 0.5;
+            let dot = saturate(radius + 0.5 - length(dist)) * saturate(min(width.x, width.y));
+            return mix(dot, share.x * share.y, max(blend.x, blend.y));
+        }
+        case LINE_STYLE_CROSSHAIR: {
+            // Only keep the lines near an intersection
+            let arm = line_alpha(dist, 2.0 * CROSSHAIR_ARM_LENGTH * cell_pixels);
+            let crosshair = max(lines.x * arm.y, lines.y * arm.x);
+            return mix(crosshair, max(share.x, share.y) * 2.0 * CROSSHAIR_ARM_LENGTH, max(blend.x, blend.y));
+        }
+        case LINE_STYLE_DASHED: {
+            // Dashes are centered on the intersections and cover half of their period
+            let dash_pixels = cell_pixels / DASHES_PER_CELL;
+            let dash_dist = abs(fract(coord / spacing * DASHES_PER_CELL - 0.5) - 0.5) * dash_pixels;
+            let dash = line_alpha(dash_dist, 0.5 * dash_pixels);
+            let dashes = max(lines.x * dash.y, lines.y * dash.x);
+            return mix(dashes, max(share.x, share.y) * 0.5, max(blend.x, blend.y));
+        }
+        default: {
+            let alpha = mix(lines, share, blend);
+            return max(alpha.x, alpha.y);
+        }
+    }
+}
+
+// Distance, in cells, from `coord` to the nearest edge of the hexagon around it. The hexagons are one
+// cell across their flats, have a vertex pointing along y, and one of them is centered on the origin.
+fn hex_edge_distance(coord: vec2<f32>) -> f32 {
+    let spacing = vec2(1.0, SQRT_3);
+    // The centers form two interleaved rectangular lattices, pick the nearest of both
+    let a = coord - round(coord / spacing) * spacing;
+    let b = coord - (floor(coord / spacing) + 0.5) * spacing;
+    let local = select(b, a, dot(a, a) < dot(b, b));
+    let hex_distance = max(abs(local.x), dot(abs(local), vec2(0.5, SQRT_3 * 0.5)));
+    return 0.5 - hex_distance;
+}
+
+// Antialiased coverage of the edges of a hexagonal or triangular lattice, given the distance to the
+// nearest edge in cells and its screen-space derivative. `density` is the share of a cell covered by
+// edges one cell wide, used for the same blend towards an even tint as line_coverage.
+fn lattice_coverage(dist: f32, dist_derivative: f32, width: f32, density: f32, cell_pixels: f32) -> f32 {
+    let alpha = line_alpha(vec2(dist / dist_derivative), vec2(width)).x;
+    return mix(alpha, saturate(width * density / cell_pixels), saturate(2.0 / cell_pixels - 1.0));
+}
+
+// Cell coordinates to draw the lines `spacing` cells apart with, given the offset from the camera
+// in cells. Lines further apart than the period of the phase use the coarse one so that they stay
+// in place.
+fn tier_coord(camera_cells: vec2<f32>, spacing: f32) -> vec2<f32> {
+    let phase = select(grid_position.coarse_cell_phase, grid_position.cell_phase, spacing <= grid_position.cell_period);
+    return phase + camera_cells;
+}
+
+// Share of the pixel around `coord` covered by the cells whose coordinates have an odd sum, box
+// filtered so that the checkerboard turns into an even mix of both colors in the distance
+fn checker_coverage(coord: vec2<f32>, derivative: vec2<f32>) -> f32 {
+    let w = derivative + 1e-4;
+    let i = 2.0 * (abs(fract((coord - 0.5 * w) * 0.5) - 0.5) - abs(fract((coord + 0.5 * w) * 0.5) - 0.5)) / w;
+    return 0.5 - 0.5 * i.x * i.y;
+}
+
+// Color of the heatmap in the cell at `coord`, premultiplied by its alpha, or transparent when the
+// cell has no value
+fn heatmap_color(cell: vec2<i32>) -> vec4<f32> {
+#ifdef GRID_HEATMAP
+    let len = grid_position.heatmap_ramp_len;
+    if len == 0u {
+        return vec4(0.0);
+    }
+    let texel = cell - grid_position.heatmap_origin;
+    if any(texel < vec2(0)) || any(texel >= vec2<i32>(textureDimensions(heatmap_texture))) {
+        return vec4(0.0);
+    }
+    let value = textureLoad(heatmap_texture, texel, 0).r;
+    // Comparisons can't be trusted to catch NaN, so look at its bits instead
+    if (bitcast<u32>(value) & 0x7fffffffu) > 0x7f800000u {
+        return vec4(0.0);
+    }
+    let range = grid_position.heatmap_range;
+    let span = select(range.y - range.x, 1e-6, range.y == range.x);
+    let position = saturate((value - range.x) / span) * f32(len - 1u);
+    let index = min(u32(position), len - 1u);
+    let start = grid_position.heatmap_ramp_start;
+    let color = mix(heatmap_ramps[start + index], heatmap_ramps[start + min(index + 1u, len - 1u)], position - f32(index));
+    return vec4(color.rgb * color.a, color.a);
+#else
+    return vec4(0.0);
+#endif
+}
+
+// Highlight of `cell` in cell_highlights, with transparent colors when it isn't highlighted
+fn find_cell_highlight(cell: vec2<i32>) -> CellHighlight {
+#ifdef GRID_CELL_HIGHLIGHTS
+    var low = grid_position.cell_highlight_start;
+    var high = low + grid_position.cell_highlight_len;
+    while low < high {
+        let middle = (low + high) / 2u;
+        let other = cell_highlights[middle].cell;
+        if all(other == cell) {
+            return cell_highlights[middle];
+        }
+        if other.y < cell.y || (other.y == cell.y && other.x < cell.x) {
+            low = middle + 1u;
+        } else {
+            high = middle;
+        }
+    }
+#endif
+    return CellHighlight(cell, vec4(0.0), vec4(0.0));
+}
+
+// Signed distance in world units from a point of the plane to the edge of the bounds, negative inside
+fn bounds_distance(plane_coords: vec2<f32>) -> f32 {
+    if grid_settings.bounds_kind == BOUNDS_CIRCLE {
+        return length(plane_coords) - grid_settings.bounds_half_size.x;
+    }
+    let q = abs(plane_coords) - grid_settings.bounds_half_size;
+    return length(max(q, vec2(0.0))) + min(max(q.x, q.y), 0.0);
+}
+
+#ifdef GRID_CLIP_POLYGON
+// Same for the clip polygon, counting how many edges a ray along x crosses to tell the inside apart
+fn clip_polygon_distance(plane_coords: vec2<f32>) -> f32 {
+    let start = grid_position.clip_polygon_start;
+    let len = grid_position.clip_polygon_len;
+    let first = plane_coords - clip_polygons[start];
+    var distance_squared = dot(first, first);
+    var side = 1.0;
+    var previous = clip_polygons[start + len - 1u];
+    for (var i = 0u; i < len; i++) {
+        let vertex = clip_polygons[start + i];
+        let edge = previous - vertex;
+        let to_point = plane_coords - vertex;
+        let closest = to_point - edge * saturate(dot(to_point, edge) / max(dot(edge, edge), 1e-12));
+        distance_squared = min(distance_squared, dot(closest, closest));
+        let crossing = vec3(
+            plane_coords.y >= vertex.y,
+            plane_coords.y < previous.y,
+            edge.x * to_point.y > edge.y * to_point.x,
+        );
+        if all(crossing) || !any(crossing) {
+            side = -side;
+        }
+        previous = vertex;
+    }
+    return side * sqrt(distance_squared);
+}
+#endif
+
+// Signed distance to the edge of the region the grid is clipped to, the intersection of its bounds
+// and clip polygon
+fn clip_distance(plane_coords: vec2<f32>) -> f32 {
+    var distance = -1e30;
+    if grid_settings.bounds_kind != BOUNDS_NONE {
+        distance = bounds_distance(plane_coords);
+    }
+#ifdef GRID_CLIP_POLYGON
+    if grid_position.clip_polygon_len > 0u {
+        distance = max(distance, clip_polygon_distance(plane_coords));
+    }
+#endif
+    return distance;
+}
+
+// Opacity left by a fade once the quantity it fades along has reached x
+fn fade(kind: u32, params: vec2<f32>, x: f32) -> f32 {
+    switch kind {
+        case FADE_LINEAR: {
+            return saturate((params.y - x) / max(params.y - params.x, 1e-6));
+        }
+        case FADE_EXPONENTIAL: {
+            return exp(-params.x * x);
+        }
+        case FADE_EXPONENTIAL_SQUARED: {
+            let density_x = params.x * x;
+            return exp(-density_x * density_x);
+        }
+        case FADE_SMOOTHSTEP: {
+            return 1.0 - smoothstep(params.x, params.y, x);
+        }
+        default: {
+            return 1.0;
+        }
+    }
+}
+
+// Same, except that linear fades keep going below 0 past their end, so that the perspective fade
+// can mix them towards 1 before clamping like the original grid did
+fn fade_unclamped(kind: u32, params: vec2<f32>, x: f32) -> f32 {
+    if kind == FADE_LINEAR {
+        return min((params.y - x) / max(params.y - params.x, 1e-6), 1.0);
+    }
+    return fade(kind, params, x);
+}
+
+// Takes the cosine of the angle between the view ray and the grid's normal, and fades along one
+// minus it, so that the default linear fade is the original min(4 cos, 1)
+fn grazing_fadeout(cos_angle: f32) -> f32 {
+    return fade(grid_settings.grazing_fade_kind, grid_settings.grazing_fade_params, 1.0 - saturate(cos_angle));
+}
+
+fn uses_distance_fog() -> bool {
+    return grid_settings.distance_fog != 0u && view.fog_mode != FOG_MODE_OFF;
+}
+
+// Same formulas as Bevy's fog, over the distance to the camera, without the light scattered by
+// directional lights
+fn apply_distance_fog(color: vec4<f32>, frag_pos_3d: vec3<f32>) -> vec4<f32> {
+    if !uses_distance_fog() {
+        return color;
+    }
+    let distance = length(frag_pos_3d);
+    var fog_color = view.fog_color;
+    if view.fog_mode == FOG_MODE_ATMOSPHERIC {
+        let extinction = 1.0 - exp(-distance * view.fog_be);
+        let inscattering = 1.0 - exp(-distance * view.fog_bi);
+        return vec4(color.rgb * (1.0 - extinction * fog_color.a) + fog_color.rgb * inscattering * fog_color.a, color.a);
+    }
+    if view.fog_mode == FOG_MODE_LINEAR {
+        fog_color.a *= 1.0 - saturate((view.fog_be.y - distance) / (view.fog_be.y - view.fog_be.x));
+    } else if view.fog_mode == FOG_MODE_EXPONENTIAL {
+        fog_color.a *= 1.0 - exp(-distance * view.fog_be.x);
+    } else if view.fog_mode == FOG_MODE_EXPONENTIAL_SQUARED {
+        let distance_times_density = distance * view.fog_be.x;
+        fog_color.a *= 1.0 - exp(-distance_times_density * distance_times_density);
+    }
+    return vec4(mix(color.rgb, fog_color.rgb, fog_color.a), color.a);
+}
+
+#ifdef GRID_SHADOWS
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// How lit a point of the plane is, from 0 in the shadow of every light to 1, weighting the lights by
+// how much they illuminate the plane there
+fn light_visibility(frag_pos_3d: vec3<f32>, frag_coord: vec2<f32>, view_z: f32, normal: vec3<f32>) -> f32 {
+    let world_position = vec4(view_bindings::view.world_position + frag_pos_3d, 1.0);
+    var lit = 0.0;
+    var total = 0.0;
+
+    for (var i = 0u; i < view_bindings::lights.n_directional_lights; i++) {
+        let light = &view_bindings::lights.directional_lights[i];
+        let weight = luminance((*light).color.rgb) * saturate(dot(normal, (*light).direction_to_light));
+        var shadow = 1.0;
+        if ((*light).flags & mesh_view_types::DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u {
+            shadow = shadows::fetch_directional_shadow(i, world_position, normal, view_z);
+        }
+        lit += weight * shadow;
+        total += weight;
+    }
+
+    let is_orthographic = view_bindings::view.clip_from_view[3].w == 1.0;
+    let cluster_index = clustering::fragment_cluster_index(frag_coord, view_z, is_orthographic);
+    let ranges = clustering::unpack_clusterable_object_index_ranges(cluster_index);
+    // Point lights come first, then spot lights. The cone of spot lights is left out of the weights.
+    for (var i = ranges.first_point_light_index_offset; i < ranges.first_reflection_probe_index_offset; i++) {
+        let light_id = clustering::get_clusterable_object_id(i);
+        let light = &view_bindings::clusterable_objects.data[light_id];
+        let to_light = (*light).position_radius.xyz - world_position.xyz;
+        let distance_squared = max(dot(to_light, to_light), 1e-4);
+        // Inverse square falloff, smoothly reaching 0 at the light's range
+        let range_factor = saturate(1.0 - pow(distance_squared * (*light).color_inverse_square_range.w, 2.0));
+        let weight = luminance((*light).color_inverse_square_range.rgb)
+            * saturate(dot(normal, to_light * inverseSqrt(distance_squared)))
+            * range_factor * range_factor / distance_squared;
+        var shadow = 1.0;
+        if ((*light).flags & mesh_view_types::POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u {
+            if i < ranges.first_spot_light_index_offset {
+                shadow = shadows::fetch_point_shadow(light_id, world_position, normal);
+            } else {
+                shadow = shadows::fetch_spot_shadow(light_id, world_position, normal, (*light).shadow_map_near_z);
+            }
+        }
+        lit += weight * shadow;
+        total += weight;
+    }
+
+    return select(1.0, lit / total, total > 0.0);
+}
+#endif
+
 struct FragmentOutput {
+#ifdef PREPASS
+    // Same targets as the prepass of Bevy's meshes
+#ifdef NORMAL_PREPASS
+    @location(0) normal: vec4<f32>,
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    @location(1) motion_vector: vec2<f32>,
+#endif
+#else
     @location(0) color: vec4<f32>,
+#endif
     @builtin(frag_depth) depth: f32,
 };
 
@@ -84,9 +555,12 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
     let planar_offset = frag_pos_3d - plane_origin;
     let rotation_matrix = grid_position.planar_rotation_matrix;
     let plane_coords = (grid_position.planar_rotation_matrix * planar_offset).xz;
+    // Same, but from the camera's projection on the plane, which keeps its precision far away
+    let camera_plane_offset = (grid_position.planar_rotation_matrix * frag_pos_3d).xz;
 
 
-    let view_space_pos = view.inverse_view * vec4(frag_pos_3d, 1.);
+    // frag_pos_3d is relative to the camera, which is the origin of the view space
+    let view_space_pos = vec4((view.inverse_view * vec4(frag_pos_3d, 0.)).xyz, 1.);
     let clip_space_pos = view.projection * view_space_pos;
     let clip_depth = clip_space_pos.z / clip_space_pos.w;
     let real_depth = -view_space_pos.z;
@@ -96,36 +570,316 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
     out.depth = clip_depth;
 
     let scale = grid_settings.scale;
-    let coord = plane_coords * scale; // use the scale variable to set the distance between the lines
+    let axis_coord = plane_coords * scale;
+#ifdef GRID_POLAR
+    // Rings around the grid origin along x and spokes along y, both counted in cells
+    let radius = length(plane_coords);
+    let angle = atan2(plane_coords.y, plane_coords.x);
+    let coord = vec2(radius / grid_settings.polar_ring_spacing, angle * grid_settings.polar_spoke_count / TAU);
+    let coord_per_world = vec2(
+        1. / grid_settings.polar_ring_spacing,
+        grid_settings.polar_spoke_count / (TAU * max(radius, 1e-6)),
+    );
+    // The angle wraps around at the negative x axis, so unwrap its derivatives
+    let angle_dx = dpdx(angle);
+    let angle_dy = dpdy(angle);
+    let angle_width = abs(angle_dx - TAU * round(angle_dx / TAU)) + abs(angle_dy - TAU * round(angle_dy / TAU));
+    let derivative = vec2(fwidth(coord.x), angle_width * grid_settings.polar_spoke_count / TAU);
+    let axis_derivative = fwidth(axis_coord);
+    // The phase is zero for polar grids, which are always drawn around their origin
+    let camera_cells = coord;
+#else
+    // Evaluated from the camera's position in cells, which the CPU reduced to a small phase
+    let camera_cells = camera_plane_offset * scale;
+    let coord = grid_position.cell_phase + camera_cells;
+    let coord_per_world = scale;
     let derivative = fwidth(coord);
-    let grid = abs(fract(coord - 0.5) - 0.5) / derivative;
-    let lne = min(grid.x, grid.y);
+    let axis_derivative = derivative;
+#endif
+
+    let minor_width = line_width_pixels(grid_settings.minor_line_width, coord_per_world, derivative);
+    let major_width = line_width_pixels(grid_settings.major_line_width, coord_per_world, derivative);
+    let minor_style = grid_settings.minor_line_style;
+    let major_style = grid_settings.major_line_style;
+    var minor_line: f32;
+    var major_line = 0.0;
+    var super_major_line = 0.0;
+#ifdef GRID_HEXAGONAL
+    // Hexagons can't be subdivided into smaller ones, so all their edges are minor lines
+    let hex_coord = select(coord, coord.yx, grid_settings.hex_flat_top != 0u);
+    let hex_distance = hex_edge_distance(hex_coord);
+    let hex_derivative = fwidth(hex_distance);
+    minor_line = lattice_coverage(
+        hex_distance,
+        hex_derivative,
+        line_width_pixels(grid_settings.minor_line_width, coord_per_world, vec2(hex_derivative)).x,
+        2.0,
+        1.0 / max(derivative.x, derivative.y),
+    );
+#else ifdef GRID_TRIANGULAR
+    // Equilateral triangles with sides one cell long, drawn as three families of parallel lines one
+    // triangle height apart. All of them are minor lines.
+    let row_height = SQRT_3 * 0.5;
+    let rows = vec3(
+        coord.y,
+        dot(coord, vec2(row_height, -0.5)),
+        dot(coord, vec2(row_height, 0.5)),
+    ) / row_height;
+    let row_distance = abs(fract(rows - 0.5) - 0.5) * row_height;
+    let row_derivative = fwidth(rows) * row_height;
+    let cell_pixels = 1.0 / max(derivative.x, derivative.y);
+    let minor_line_width = grid_settings.minor_line_width;
+    for (var i = 0; i < 3; i++) {
+        minor_line = max(minor_line, lattice_coverage(
+            row_distance[i],
+            row_derivative[i],
+            line_width_pixels(minor_line_width, coord_per_world, vec2(row_derivative[i])).x,
+            1.0 / row_height,
+            cell_pixels,
+        ));
+    }
+#else
+    let lod_base = grid_settings.lod_base;
+    if lod_base > 1.0 {
+        // Pick the finest power of the base that is still at least LOD_MIN_CELL_PIXELS apart on
+        // screen. It fades out as it gets denser while the coarser powers take over, so that the
+        // lines at fade 1 match the lines at fade 0 of the next level.
+        let min_cell_pixels = max(LOD_MIN_CELL_PIXELS, 4.0 * max(minor_width.x, minor_width.y));
+        let lod = log2(max(derivative.x, derivative.y) * min_cell_pixels) / log2(lod_base);
+        let lod_fade = fract(lod);
+        let spacing = pow(lod_base, floor(lod) + 1.0);
+        minor_line = max(
+            line_coverage(tier_coord(camera_cells, spacing), derivative, spacing, minor_width, minor_style) * (1.0 - lod_fade),
+            line_coverage(tier_coord(camera_cells, spacing * lod_base), derivative, spacing * lod_base, minor_width, minor_style),
+        );
+        let major_spacing = spacing * lod_base * lod_base;
+        major_line = max(
+            line_coverage(tier_coord(camera_cells, major_spacing), derivative, major_spacing, major_width, major_style) * (1.0 - lod_fade),
+            line_coverage(tier_coord(camera_cells, major_spacing * lod_base), derivative, major_spacing * lod_base, major_width, major_style),
+        );
+    } else {
+        let major_every = grid_settings.major_line_every;
+        minor_line = line_coverage(coord, derivative, 1.0, minor_width, minor_style);
+        major_line = line_coverage(coord, derivative, major_every, major_width, major_style);
+        if grid_settings.super_major_line_every > 0.0 {
+            super_major_line = line_coverage(
+                coord,
+                derivative,
+                major_every * grid_settings.super_major_line_every,
+                line_width_pixels(grid_settings.super_major_line_width, coord_per_world, derivative),
+                LINE_STYLE_SOLID,
+            );
+        }
+    }
+#endif
 
-    let derivative2 = fwidth(coord * 0.1);
-    let grid2 = abs(fract((coord * 0.1) - 0.5) - 0.5) / derivative2;
-    let mg_line = min(grid2.x, grid2.y);
+#ifdef GRID_HEXAGONAL
+    let checker = 0.0;
+#else ifdef GRID_TRIANGULAR
+    // The triangles pointing either way are told apart by the parity of the rows they are in
+    let row_floors = floor(rows);
+    let checker = mix(fract((row_floors.x + row_floors.y + row_floors.z) * 0.5) * 2.0, 0.5, saturate(2.0 / cell_pixels - 1.0));
+#else
+    let checker = checker_coverage(coord, derivative);
+#endif
 
-    let grid3 = abs(coord) / derivative;
-    let axis_line = min(grid3.x, grid3.y);
+    // The axes stay on the grid origin even when the cells are offset
+    let grid3 = abs(axis_coord) / axis_derivative;
+    let axis_alpha = line_alpha(grid3, line_width_pixels(grid_settings.axis_line_width, scale, axis_derivative));
+    let axis_line = max(axis_alpha.x, axis_alpha.y);
 
-    var alpha = vec3(1.0) - min(vec3(axis_line, mg_line, lne), vec3(1.0));
-    alpha.y *= (1.0 - alpha.x) * grid_settings.major_line_col.a;
-    alpha.z *= (1.0 - (alpha.x + alpha.y)) * grid_settings.minor_line_col.a;
+    var alpha = vec4(axis_line, super_major_line, major_line, minor_line);
+    alpha.y *= (1.0 - alpha.x) * grid_settings.super_major_line_col.a;
+    alpha.z *= (1.0 - (alpha.x + alpha.y)) * grid_settings.major_line_col.a;
+    alpha.w *= (1.0 - (alpha.x + alpha.y + alpha.z)) * grid_settings.minor_line_col.a;
 
-    let dist_fadeout = min(1., 1. - grid_settings.dist_fadeout_const * real_depth);
-    let dot_fadeout = abs(dot(grid_position.normal, normalize(view.world_position - frag_pos_3d)));
-    let alpha_fadeout = mix(dist_fadeout, 1., dot_fadeout) * min(grid_settings.dot_fadeout_const * dot_fadeout, 1.);
+#ifdef VIEW_PROJECTION_ORTHOGRAPHIC
+    // Depth is meaningless in an orthographic view, so fade the whole grid out as the view zooms out
+    // instead, using the half extent of the view in world units
+    let view_extent = max(1. / view.projection[0].x, 1. / view.projection[1].y);
+    let dist_fadeout = select(fade(grid_settings.fade_kind, grid_settings.fade_params, view_extent), 1., uses_distance_fog());
+    // All rays are parallel, and the ones that hit the plane behind the near plane must not draw it
+    let dot_fadeout = abs(dot(grid_position.normal, ray_direction));
+    let alpha_fadeout = dist_fadeout * grazing_fadeout(dot_fadeout) * f32(t >= 0.);
+    let fill_fadeout = select(fade(grid_settings.fill_fade_kind, grid_settings.fill_fade_params, view_extent), 1., uses_distance_fog()) * f32(t >= 0.);
+#else
+    // The fog takes over from the distance fadeout
+    let dist_fadeout = select(fade_unclamped(grid_settings.fade_kind, grid_settings.fade_params, real_depth), 1., uses_distance_fog());
+    let dot_fadeout = abs(dot(grid_position.normal, normalize(-frag_pos_3d)));
+    // Looking straight at the plane, the distance fade is ignored. Clamped only once mixed, so
+    // that a linear fade still reaches 0 at grazing angles past its end.
+    let alpha_fadeout = saturate(mix(dist_fadeout, 1., dot_fadeout)) * grazing_fadeout(dot_fadeout);
+    let fill_fadeout = select(fade(grid_settings.fill_fade_kind, grid_settings.fill_fade_params, real_depth), 1., uses_distance_fog());
+#endif
 
-    let a_0 = alpha.x + alpha.y + alpha.z;
+    let a_0 = alpha.x + alpha.y + alpha.z + alpha.w;
     alpha /= a_0;
     // On MacOS the line above could generate NaNs and render as black instead of transparent
-    alpha = clamp(alpha, vec3(0.0), vec3(1.0));
+    alpha = clamp(alpha, vec4(0.0), vec4(1.0));
     let axis_color = mix(grid_settings.x_axis_col, grid_settings.z_axis_col, step(grid3.x, grid3.y));
     var grid_color = vec4(
-        axis_color * alpha.x + grid_settings.major_line_col.rgb * alpha.y + grid_settings.minor_line_col.rgb * alpha.z,
+        axis_color * alpha.x
+            + grid_settings.super_major_line_col.rgb * alpha.y
+            + grid_settings.major_line_col.rgb * alpha.z
+            + grid_settings.minor_line_col.rgb * alpha.w,
         max(a_0 * alpha_fadeout, 0.0),
     );
-    out.color = grid_color;
+
+    // The fill goes under the lines, mixing both colors premultiplied by their alpha
+    let fill_col = grid_settings.fill_col;
+    let fill_checker_col = grid_settings.fill_checker_col;
+    let fill = mix(
+        vec4(fill_col.rgb * fill_col.a, fill_col.a),
+        vec4(fill_checker_col.rgb * fill_checker_col.a, fill_checker_col.a),
+        checker,
+    ) * fill_fadeout;
+    // The overlay image goes between the fill and the lines, antialiased along its edges
+    let overlay_uv = grid_position.overlay_from_plane * (camera_plane_offset - grid_position.overlay_origin);
+    let overlay_edge = min(overlay_uv, 1.0 - overlay_uv) / fwidth(overlay_uv);
+    let overlay_sample = textureSample(overlay_texture, overlay_sampler, overlay_uv);
+    let overlay_alpha = overlay_sample.a * saturate(min(overlay_edge.x, overlay_edge.y) + 0.5)
+        * f32(grid_position.overlay != 0u) * fill_fadeout;
+    var background = vec4(overlay_sample.rgb * overlay_alpha, overlay_alpha) + fill * (1.0 - overlay_alpha);
+    // Then the heatmap's cells, and the fill of the highlighted ones
+    let cell = vec2<i32>(floor(coord)) + grid_position.cell_shift;
+    let heatmap = heatmap_color(cell) * fill_fadeout;
+    background = heatmap + background * (1.0 - heatmap.a);
+    let highlight = find_cell_highlight(cell);
+    let highlight_fill = vec4(highlight.fill_col.rgb * highlight.fill_col.a, highlight.fill_col.a) * fill_fadeout;
+    background = highlight_fill + background * (1.0 - highlight_fill.a);
+    let filled_alpha = grid_color.a + background.a * (1.0 - grid_color.a);
+    grid_color = vec4(
+        (grid_color.rgb * grid_color.a + background.rgb * (1.0 - grid_color.a)) / max(filled_alpha, 1e-6),
+        filled_alpha,
+    );
+
+    // The outlines of the highlighted cells go over the lines, along the inside of their edges
+    let cell_fract = fract(coord);
+    let edge_pixels = min(cell_fract, 1.0 - cell_fract) / derivative;
+    let outline_width = line_width_pixels(grid_position.cell_highlight_outline_width, coord_per_world, derivative);
+    let outline_alphas = line_alpha(edge_pixels, 2.0 * outline_width);
+    let outline = max(outline_alphas.x, outline_alphas.y) * highlight.outline_col.a * fill_fadeout;
+    let outlined_alpha = outline + grid_color.a * (1.0 - outline);
+    grid_color = vec4(
+        (highlight.outline_col.rgb * outline + grid_color.rgb * grid_color.a * (1.0 - outline)) / max(outlined_alpha, 1e-6),
+        outlined_alpha,
+    );
+
+    // The cursor goes over everything else, with a crosshair along the lines through it that ends
+    // crosshair_length cells away, and a circle around it
+    let to_cursor = coord - grid_position.cursor_coord;
+    let cursor_pixels = to_cursor / derivative;
+    // Sized in pixels like the circle, whatever the unit of the grid lines
+    let cursor_width = vec2(grid_settings.cursor_line_width);
+    let crosshair_lines = line_alpha(abs(cursor_pixels), cursor_width);
+    let crosshair_extent = saturate((grid_settings.cursor_crosshair_length - abs(to_cursor)) / derivative + 0.5);
+    let crosshair = max(crosshair_lines.x * crosshair_extent.y, crosshair_lines.y * crosshair_extent.x)
+        * f32(grid_settings.cursor_crosshair_length > 0.0);
+    let circle_distance = abs(length(cursor_pixels) - grid_settings.cursor_circle_radius);
+    let circle = line_alpha(vec2(circle_distance), cursor_width).x * f32(grid_settings.cursor_circle_radius > 0.0);
+    let cursor = max(crosshair, circle) * grid_settings.cursor_col.a * f32(grid_position.cursor != 0u) * fill_fadeout;
+    let cursor_alpha = cursor + grid_color.a * (1.0 - cursor);
+    grid_color = vec4(
+        (grid_settings.cursor_col.rgb * cursor + grid_color.rgb * grid_color.a * (1.0 - cursor)) / max(cursor_alpha, 1e-6),
+        cursor_alpha,
+    );
+
+#ifdef GRID_SHADOWS
+    // Lit from the side the camera is on
+    let lit_normal = plane_normal * sign(dot(plane_normal, -frag_pos_3d));
+    let shadow = 1.0 - light_visibility(frag_pos_3d, in.clip_position.xy, view_space_pos.z, lit_normal);
+    grid_color = vec4(grid_color.rgb * (1.0 - grid_settings.shadow_darkening * shadow), grid_color.a);
+    // The shadow color goes under the lines, over the whole plane
+    let tint = grid_settings.shadow_col.a * shadow * alpha_fadeout;
+    let tinted_alpha = grid_color.a + tint * (1.0 - grid_color.a);
+    grid_color = vec4(
+        (grid_color.rgb * grid_color.a + grid_settings.shadow_col.rgb * tint * (1.0 - grid_color.a)) / max(tinted_alpha, 1e-6),
+        tinted_alpha,
+    );
+#endif
+
+    let clipped = grid_settings.bounds_kind != BOUNDS_NONE || grid_position.clip_polygon_len > 0u;
+    let edge_distance = clip_distance(plane_coords);
+    let edge_derivative = fwidth(edge_distance);
+    if clipped {
+        // Fade the lines out over the falloff band inside the bounds, and at least antialias the edge
+        let inside = saturate((0.5 * edge_derivative - edge_distance) / (grid_settings.bounds_falloff + edge_derivative));
+        let border_width = line_width_pixels(grid_settings.border_line_width, vec2(1.), vec2(edge_derivative)).x;
+        let border = line_alpha(vec2(abs(edge_distance) / edge_derivative), vec2(border_width)).x
+            * grid_settings.border_col.a * alpha_fadeout;
+        // The border goes over the lines
+        let lines = grid_color.a * inside;
+        let bounded_alpha = border + lines * (1.0 - border);
+        grid_color = vec4(
+            (grid_settings.border_col.rgb * border + grid_color.rgb * lines * (1.0 - border)) / max(bounded_alpha, 1e-6),
+            bounded_alpha,
+        );
+    }
+
+#ifdef ALPHA_MASK
+    // Masked grids are drawn with the opaque geometry, so what they cover must be fully opaque.
+    // Rays that hit the plane behind the camera, or never do, get their depth clamped into range
+    // and would pass the depth test, so they are discarded whatever the cutoff.
+    let hits_plane = t > 0.0 && abs(denominator) > 1e-6;
+    if !hits_plane || grid_color.a < grid_settings.alpha_mask_cutoff || (clipped && edge_distance > 0.0) {
+        discard;
+    }
+    grid_color.a = 1.0;
+#endif
+
+#ifdef PREPASS
+#ifdef NORMAL_PREPASS
+    // The plane is two-sided, so its normal faces the camera
+    let facing_normal = plane_normal * sign(dot(plane_normal, -frag_pos_3d));
+    out.normal = vec4(facing_normal * 0.5 + vec3(0.5), 1.0);
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    let previous_clip_space_pos = view.previous_projection * vec4(frag_pos_3d, 1.);
+    out.motion_vector = (clip_space_pos.xy / clip_space_pos.w
+        - previous_clip_space_pos.xy / previous_clip_space_pos.w) * vec2(0.5, -0.5);
+#endif
+#else
+    out.color = apply_distance_fog(grid_color, frag_pos_3d);
+#endif
+
+    return out;
+}
+
+#ifndef PREPASS
+// Draws the grid's vertical axis, an infinite line through its origin along its normal, by finding
+// where each ray passes closest to it
+@fragment
+fn fragment_y_axis(in: VertexOutput) -> FragmentOutput {
+    let ray_origin = in.near_point;
+    let ray_direction = normalize(in.far_point - in.near_point);
+    let axis_direction = grid_position.normal;
+
+    let origin_offset = ray_origin - grid_position.origin;
+    let b = dot(ray_direction, axis_direction);
+    let d = dot(ray_direction, origin_offset);
+    let e = dot(axis_direction, origin_offset);
+    // The ray and the axis are parallel when looking straight along it, closest points are then
+    // anywhere along the ray
+    let denominator = 1. - b * b;
+    let parallel = denominator < 1e-6;
+    let t = select((b * e - d) / denominator, 0., parallel);
+    let s = select((e - b * d) / denominator, e, parallel);
+    let ray_point = ray_origin + ray_direction * t;
+    let axis_distance = distance(ray_point, grid_position.origin + axis_direction * s);
+
+    let view_space_pos = vec4((view.inverse_view * vec4(ray_point, 0.)).xyz, 1.);
+    let clip_space_pos = view.projection * view_space_pos;
+
+    var out: FragmentOutput;
+    out.depth = clip_space_pos.z / clip_space_pos.w;
+
+    let distance_derivative = fwidth(axis_distance);
+    let width = line_width_pixels(grid_settings.axis_line_width, vec2(1.), vec2(distance_derivative));
+    let axis_line = line_alpha(vec2(axis_distance / distance_derivative), width).x;
+    let fadeout = saturate(1. - grid_settings.y_axis_dist_fadeout_const * abs(s));
+    // Nothing behind the near plane is drawn
+    out.color = apply_distance_fog(vec4(grid_settings.y_axis_col, axis_line * fadeout * f32(t >= 0.)), ray_point);
 
     return out;
 }
+#endif
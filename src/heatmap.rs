@@ -0,0 +1,221 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    math::I64Vec2,
+    platform::collections::HashMap,
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        renderer::RenderDevice,
+    },
+};
+
+/// Colors the cells of a [`GridKind::Cartesian`](crate::GridKind::Cartesian) grid by a scalar
+/// value, on top of its [`fill`](crate::InfiniteGridSettings::fill) and
+/// [`GridImageOverlay`](crate::GridImageOverlay) and under its lines, fading out along with the
+/// fill. Other kinds of grids ignore it, and so do devices without storage buffers like WebGL2.
+///
+/// Cells are indexed by how many cells they are from the grid's translation along its local X
+/// and Z axes, shifted by its [`cell_offset`](crate::InfiniteGridSettings::cell_offset), so that
+/// the cell `(0, 0)` starts on the offset and extends towards positive X and Z.
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct GridHeatmap {
+    pub values: HeatmapValues,
+    /// Colors evenly spread over `range`, interpolated in linear space. Values outside of it get
+    /// the color of the nearest end.
+    pub ramp: Vec<Color>,
+    /// Values mapped to the first and the last color of the ramp.
+    pub range: Vec2,
+}
+
+/// Where the values of a [`GridHeatmap`] come from. Cells without a value are left uncolored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeatmapValues {
+    /// One value per cell in the first channel of a float image, like
+    /// [`TextureFormat::R32Float`]. Its texel `(0, 0)` is the cell `origin`, its x axis runs along
+    /// the grid's local X axis and its y axis along Z. Texels that are NaN have no value.
+    Image { image: Handle<Image>, origin: IVec2 },
+    /// Values of individual cells, packed into an image covering all of them. That image has to
+    /// fit in a texture, so keep the cells within a few thousand of each other: cells spanning
+    /// more than the device's largest texture, or more than 16 million in all, leave the heatmap
+    /// undrawn with a warning.
+    Cells(HashMap<IVec2, f32>),
+}
+
+impl Default for HeatmapValues {
+    fn default() -> Self {
+        Self::Cells(HashMap::default())
+    }
+}
+
+// Most cells a packed image may cover, empty ones included, which keeps it at 64 MiB
+const MAX_PACKED_HEATMAP_CELLS: u64 = 1 << 24;
+
+/// Image the cells of a [`HeatmapValues::Cells`] are packed into, with the cell of its first texel.
+#[derive(Component, Clone, Debug)]
+pub(crate) struct PackedHeatmapCells {
+    pub(crate) image: Handle<Image>,
+    pub(crate) origin: IVec2,
+}
+
+pub(crate) fn pack_heatmap_cells(
+    mut commands: Commands,
+    heatmaps: Query<(Entity, &GridHeatmap, Option<&PackedHeatmapCells>), Changed<GridHeatmap>>,
+    mut removed_heatmaps: RemovedComponents<GridHeatmap>,
+    mut images: ResMut<Assets<Image>>,
+    render_device: Option<Res<RenderDevice>>,
+) {
+    // Dropping the handle frees the image
+    for entity in removed_heatmaps.read() {
+        commands.entity(entity).try_remove::<PackedHeatmapCells>();
+    }
+
+    // WebGL2's limit when there's no renderer to ask
+    let max_size = render_device.map_or(2048, |render_device| {
+        render_device.limits().max_texture_dimension_2d
+    }) as i64;
+    for (entity, heatmap, packed) in &heatmaps {
+        let HeatmapValues::Cells(cells) = &heatmap.values else {
+            if packed.is_some() {
+                commands.entity(entity).remove::<PackedHeatmapCells>();
+            }
+            continue;
+        };
+        let packed_values = match pack_values(cells, max_size) {
+            Ok(Some(packed_values)) => packed_values,
+            Ok(None) => {
+                commands.entity(entity).remove::<PackedHeatmapCells>();
+                continue;
+            }
+            Err(extent) => {
+                warn!(
+                    "Heatmap cells of {entity} span {} by {} cells, which doesn't fit in a texture",
+                    extent.x, extent.y
+                );
+                commands.entity(entity).remove::<PackedHeatmapCells>();
+                continue;
+            }
+        };
+        let image = Image::new(
+            Extent3d {
+                width: packed_values.size.x,
+                height: packed_values.size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            packed_values
+                .values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            TextureFormat::R32Float,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+        // Reuse the image of the previous packing so that it's replaced instead of piling up
+        let handle = match packed.filter(|packed| images.contains(&packed.image)) {
+            Some(packed) => {
+                let _ = images.insert(&packed.image, image);
+                packed.image.clone()
+            }
+            None => images.add(image),
+        };
+        commands.entity(entity).insert(PackedHeatmapCells {
+            image: handle,
+            origin: packed_values.origin,
+        });
+    }
+}
+
+/// Values of cells laid out row by row from the cell `origin`, NaN where a cell has none.
+#[derive(Debug, PartialEq)]
+struct PackedValues {
+    origin: IVec2,
+    size: UVec2,
+    values: Vec<f32>,
+}
+
+/// Packs `cells` into the smallest rectangle covering them, or `None` when there are none. Fails
+/// with the extent of that rectangle when it's wider or taller than `max_size`, or covers more
+/// than [`MAX_PACKED_HEATMAP_CELLS`].
+fn pack_values(
+    cells: &HashMap<IVec2, f32>,
+    max_size: i64,
+) -> Result<Option<PackedValues>, I64Vec2> {
+    let Some((min, max)) = cells.keys().fold(None, |bounds, &cell| {
+        Some(bounds.map_or((cell, cell), |(min, max): (IVec2, IVec2)| {
+            (min.min(cell), max.max(cell))
+        }))
+    }) else {
+        return Ok(None);
+    };
+
+    // Cells far apart overflow an i32 extent
+    let extent = max.as_i64vec2() - min.as_i64vec2() + 1;
+    if extent.max_element() > max_size
+        || (extent.x as u64).saturating_mul(extent.y as u64) > MAX_PACKED_HEATMAP_CELLS
+    {
+        return Err(extent);
+    }
+    let size = extent.as_uvec2();
+    let mut values = vec![f32::NAN; (size.x * size.y) as usize];
+    for (&cell, &value) in cells {
+        let texel = (cell - min).as_uvec2();
+        values[(texel.y * size.x + texel.x) as usize] = value;
+    }
+    Ok(Some(PackedValues {
+        origin: min,
+        size,
+        values,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_cells_row_by_row() {
+        let cells = HashMap::from_iter([
+            (IVec2::new(-1, 3), 1.),
+            (IVec2::new(1, 3), 2.),
+            (IVec2::new(0, 4), 3.),
+        ]);
+        let packed = pack_values(&cells, 2048).unwrap().unwrap();
+        assert_eq!(packed.origin, IVec2::new(-1, 3));
+        assert_eq!(packed.size, UVec2::new(3, 2));
+        assert_eq!(packed.values[0], 1.);
+        assert_eq!(packed.values[2], 2.);
+        assert_eq!(packed.values[4], 3.);
+    }
+
+    #[test]
+    fn fills_missing_cells_with_nan() {
+        let cells = HashMap::from_iter([(IVec2::ZERO, 0.5), (IVec2::new(2, 2), 1.5)]);
+        let packed = pack_values(&cells, 2048).unwrap().unwrap();
+        assert_eq!(packed.size, UVec2::splat(3));
+        let missing = packed.values.iter().filter(|value| value.is_nan()).count();
+        assert_eq!(missing, 7);
+        assert_eq!(packed.values[0], 0.5);
+        assert_eq!(packed.values[8], 1.5);
+    }
+
+    #[test]
+    fn no_cells() {
+        assert_eq!(pack_values(&HashMap::default(), 2048), Ok(None));
+    }
+
+    #[test]
+    fn refuses_cells_too_far_apart() {
+        let cells = HashMap::from_iter([(IVec2::ZERO, 0.), (IVec2::new(2048, 0), 0.)]);
+        assert_eq!(pack_values(&cells, 2048), Err(I64Vec2::new(2049, 1)));
+
+        // Overflows an i32 extent
+        let cells = HashMap::from_iter([(IVec2::MIN, 0.), (IVec2::MAX, 0.)]);
+        assert_eq!(pack_values(&cells, i64::MAX), Err(I64Vec2::splat(1 << 32)));
+
+        // Fits in a texture but covers too many cells
+        let cells = HashMap::from_iter([(IVec2::ZERO, 0.), (IVec2::splat(4096), 0.)]);
+        assert_eq!(pack_values(&cells, 16384), Err(I64Vec2::splat(4097)));
+        let cells = HashMap::from_iter([(IVec2::ZERO, 0.), (IVec2::splat(4095), 0.)]);
+        assert!(pack_values(&cells, 16384).unwrap().is_some());
+    }
+}
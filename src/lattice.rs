@@ -0,0 +1,215 @@
+use bevy::prelude::*;
+
+use crate::{GridCell, GridPlane, HexOrientation, InfiniteGridSettings};
+
+const SQRT_3: f32 = 1.732_050_8;
+
+/// A cell of a [`GridKind::Triangular`](crate::GridKind::Triangular) grid. Each rhombus of the
+/// lattice spanned by the grid's local X axis and the direction 60° from it towards Z is split
+/// into two triangles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TriangleCell {
+    /// Coordinates of the rhombus along the two lattice directions.
+    pub rhombus: IVec2,
+    /// Whether the triangle points along the grid's local Z axis, i.e. is the lower half of the
+    /// rhombus.
+    pub points_up: bool,
+}
+
+impl InfiniteGridSettings {
+    /// Axial coordinates of the cell of a [`GridKind::Hexagonal`](crate::GridKind::Hexagonal) grid
+    /// placed at `transform` that contains `world_position` once projected onto the grid plane, or
+    /// `None` for other kinds.
+    ///
+    /// The axial coordinates follow the usual convention for each orientation, and `(0, 0)` is
+    /// the hexagon on the grid's [`cell_offset`](Self::cell_offset). With pointy top hexagons `q`
+    /// counts along the rows, which run along the grid's local X axis, and `r` along the
+    /// direction 60° from X towards Z. With flat top ones `r` counts along the columns, which run
+    /// along Z, and `q` along the direction 30° from X towards Z.
+    ///
+    /// The grid's [`GridFloatingOrigin`](crate::GridFloatingOrigin) is ignored, use
+    /// [`InfiniteGrids`](crate::InfiniteGrids) for grids that have one.
+    pub fn world_to_hex_cell(
+        &self,
+        transform: &GlobalTransform,
+        world_position: Vec3,
+    ) -> Option<IVec2> {
+        match GridPlane::new(self, transform, None).world_to_cell(world_position) {
+            GridCell::Hex(cell) => Some(cell),
+            _ => None,
+        }
+    }
+
+    /// The cell of a [`GridKind::Triangular`](crate::GridKind::Triangular) grid placed at
+    /// `transform` that contains `world_position` once projected onto the grid plane, or `None`
+    /// for other kinds.
+    ///
+    /// The grid's [`GridFloatingOrigin`](crate::GridFloatingOrigin) is ignored, use
+    /// [`InfiniteGrids`](crate::InfiniteGrids) for grids that have one.
+    pub fn world_to_triangle_cell(
+        &self,
+        transform: &GlobalTransform,
+        world_position: Vec3,
+    ) -> Option<TriangleCell> {
+        match GridPlane::new(self, transform, None).world_to_cell(world_position) {
+            GridCell::Triangle(cell) => Some(cell),
+            _ => None,
+        }
+    }
+}
+
+/// The hexagon at `coords`, measured in cells like the shader does.
+pub(crate) fn hex_cell(orientation: HexOrientation, coords: Vec2) -> IVec2 {
+    // Flat top hexagons are pointy top ones with their axes swapped, as in the shader
+    match orientation {
+        HexOrientation::PointyTop => pointy_hex_cell(coords),
+        HexOrientation::FlatTop => pointy_hex_cell(coords.yx()).yx(),
+    }
+}
+
+/// The triangle at `coords`, measured in cells like the shader does.
+pub(crate) fn triangle_cell(coords: Vec2) -> TriangleCell {
+    let b = coords.y * 2. / SQRT_3;
+    let lattice = Vec2::new(coords.x - b * 0.5, b);
+    let rhombus = lattice.floor();
+    let within = lattice - rhombus;
+    TriangleCell {
+        rhombus: rhombus.as_ivec2(),
+        points_up: within.x + within.y < 1.,
+    }
+}
+
+/// Axial coordinates of the pointy top hexagon, one unit across its flats, that contains `coords`.
+fn pointy_hex_cell(coords: Vec2) -> IVec2 {
+    let r = coords.y * 2. / SQRT_3;
+    let q = coords.x - r * 0.5;
+    // Round in cube coordinates, then fix up the component that moved the most so that they
+    // still sum to zero
+    let cube = Vec3::new(q, r, -q - r);
+    let rounded = cube.round();
+    let error = (rounded - cube).abs();
+    if error.x > error.y && error.x > error.z {
+        IVec2::new((-rounded.y - rounded.z) as i32, rounded.y as i32)
+    } else if error.y > error.z {
+        IVec2::new(rounded.x as i32, (-rounded.x - rounded.z) as i32)
+    } else {
+        IVec2::new(rounded.x as i32, rounded.y as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GridKind;
+
+    fn hex_settings(orientation: HexOrientation) -> InfiniteGridSettings {
+        InfiniteGridSettings {
+            kind: GridKind::Hexagonal { orientation },
+            ..default()
+        }
+    }
+
+    // Center of a hexagon, on the plane, following the convention of world_to_hex_cell
+    fn hex_center(orientation: HexOrientation, cell: IVec2) -> Vec2 {
+        let cell = cell.as_vec2();
+        match orientation {
+            HexOrientation::PointyTop => Vec2::new(cell.x + cell.y * 0.5, cell.y * SQRT_3 * 0.5),
+            HexOrientation::FlatTop => Vec2::new(cell.x * SQRT_3 * 0.5, cell.x * 0.5 + cell.y),
+        }
+    }
+
+    fn hex_cell(orientation: HexOrientation, point: Vec2) -> IVec2 {
+        hex_settings(orientation)
+            .world_to_hex_cell(&GlobalTransform::IDENTITY, point.extend(0.).xzy())
+            .unwrap()
+    }
+
+    #[test]
+    fn hex_centers() {
+        for orientation in [HexOrientation::PointyTop, HexOrientation::FlatTop] {
+            for q in -3..=3 {
+                for r in -3..=3 {
+                    let cell = IVec2::new(q, r);
+                    assert_eq!(hex_cell(orientation, hex_center(orientation, cell)), cell);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hex_edges() {
+        let pointy = HexOrientation::PointyTop;
+        assert_eq!(hex_cell(pointy, Vec2::new(0.499, 0.)), IVec2::ZERO);
+        assert_eq!(hex_cell(pointy, Vec2::new(0.501, 0.)), IVec2::new(1, 0));
+        assert_eq!(hex_cell(pointy, Vec2::new(-0.501, 0.)), IVec2::new(-1, 0));
+        let flat = HexOrientation::FlatTop;
+        assert_eq!(hex_cell(flat, Vec2::new(0., 0.499)), IVec2::ZERO);
+        assert_eq!(hex_cell(flat, Vec2::new(0., 0.501)), IVec2::new(0, 1));
+        assert_eq!(hex_cell(flat, Vec2::new(0., -0.501)), IVec2::new(0, -1));
+    }
+
+    #[test]
+    fn hex_corners() {
+        for orientation in [HexOrientation::PointyTop, HexOrientation::FlatTop] {
+            // The three hexagons around a corner of the one on the origin
+            let cells = match orientation {
+                HexOrientation::PointyTop => [IVec2::ZERO, IVec2::new(0, 1), IVec2::new(-1, 1)],
+                HexOrientation::FlatTop => [IVec2::ZERO, IVec2::new(1, 0), IVec2::new(1, -1)],
+            };
+            let corner = cells
+                .iter()
+                .map(|&cell| hex_center(orientation, cell))
+                .sum::<Vec2>()
+                / 3.;
+            for cell in cells {
+                let toward_center = (hex_center(orientation, cell) - corner).normalize();
+                let point = corner + toward_center * 1e-3;
+                assert_eq!(hex_cell(orientation, point), cell);
+            }
+        }
+    }
+
+    #[test]
+    fn hex_cell_offset() {
+        let settings = InfiniteGridSettings {
+            cell_offset: Vec2::new(10., 0.),
+            ..hex_settings(HexOrientation::PointyTop)
+        };
+        let cell = settings.world_to_hex_cell(&GlobalTransform::IDENTITY, Vec3::new(11., 0., 0.));
+        assert_eq!(cell, Some(IVec2::new(1, 0)));
+    }
+
+    #[test]
+    fn triangle_cells() {
+        let settings = InfiniteGridSettings {
+            kind: GridKind::Triangular,
+            ..default()
+        };
+        let cell = |x: f32, z: f32| {
+            settings
+                .world_to_triangle_cell(&GlobalTransform::IDENTITY, Vec3::new(x, 0., z))
+                .unwrap()
+        };
+        assert_eq!(
+            cell(0.5, 0.1),
+            TriangleCell {
+                rhombus: IVec2::ZERO,
+                points_up: true,
+            }
+        );
+        assert_eq!(
+            cell(1., 0.5),
+            TriangleCell {
+                rhombus: IVec2::ZERO,
+                points_up: false,
+            }
+        );
+        assert_eq!(
+            cell(-0.5, -0.1),
+            TriangleCell {
+                rhombus: IVec2::new(-1, -1),
+                points_up: false,
+            }
+        );
+    }
+}
@@ -1,15 +1,36 @@
+mod heatmap;
+mod lattice;
+mod plane;
 mod render;
 
 use bevy::{
-    camera::visibility::{self, NoFrustumCulling, VisibilityClass},
+    camera::{
+        primitives::Aabb,
+        visibility::{self, NoFrustumCulling, VisibilityClass, VisibilitySystems},
+    },
+    ecs::entity::EntityHashSet,
+    math::I64Vec3,
     prelude::*,
     render::{sync_world::SyncToRenderWorld, view::RenderVisibleEntities},
 };
 
+pub use bundle::InfiniteGridBundle;
+pub use heatmap::{GridHeatmap, HeatmapValues};
+pub use lattice::TriangleCell;
+pub use plane::{GridCell, GridLineTier, GridPlane, InfiniteGrids};
+
 pub struct InfiniteGridPlugin;
 
 impl Plugin for InfiniteGridPlugin {
-    fn build(&self, _app: &mut App) {}
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (
+                update_grid_aabbs.in_set(VisibilitySystems::CalculateBounds),
+                heatmap::pack_heatmap_cells,
+            ),
+        );
+    }
 
     fn finish(&self, app: &mut App) {
         render::render_app_builder(app);
@@ -19,43 +40,506 @@ impl Plugin for InfiniteGridPlugin {
 #[derive(Component, Default)]
 pub struct InfiniteGrid;
 
-#[derive(Component, Copy, Clone)]
+#[derive(Component, Copy, Clone, Debug)]
 #[require(VisibilityClass)]
 #[component(on_add = visibility::add_visibility_class::<InfiniteGridSettings>)]
 pub struct InfiniteGridSettings {
+    pub kind: GridKind,
     pub x_axis_color: Color,
+    /// Color of the vertical axis, drawn through the grid's translation along its normal, if any.
+    pub y_axis_color: Option<Color>,
     pub z_axis_color: Color,
     pub minor_line_color: Color,
     pub major_line_color: Color,
+    pub super_major_line_color: Color,
+    /// What is drawn behind the lines, over the whole plane.
+    pub fill: GridFill,
+    /// How the grid fades out with the distance from the camera, in world units. Orthographic
+    /// cameras fade the whole grid out with the half extent of their view instead, as they zoom
+    /// out.
+    pub fade: GridFade,
+    /// Fades the grid into the camera's [`DistanceFog`] like the meshes of the scene, instead of
+    /// with [`fade`](Self::fade), when the camera has one. The light that directional lights
+    /// scatter in the fog isn't included.
+    pub distance_fog: bool,
+    /// How the grid fades out towards the horizon, along one minus the cosine of the angle
+    /// between the view ray and the grid's normal, which goes from 0 looking straight at the plane
+    /// to 1 along it. The default fades out over the last quarter, at angles past about 75°.
+    pub grazing_fade: GridFade,
+    /// How the fill fades out with the distance from the camera, like [`fade`](Self::fade) but
+    /// independently from the lines. The fill doesn't fade towards the horizon.
+    pub fill_fade: GridFade,
+    /// Distance at which the grid has faded out, while [`fade`](Self::fade) is left at its
+    /// default.
+    #[deprecated(
+        note = "use `fade: GridFade::Linear { start: 0., end: fadeout_distance }` instead"
+    )]
     pub fadeout_distance: f32,
+    /// Cosine of the angle to the normal below which the grid fades out towards the horizon, while
+    /// [`grazing_fade`](Self::grazing_fade) is left at its default.
+    #[deprecated(
+        note = "use `grazing_fade: GridFade::Linear { start: 1. - dot_fadeout_strength, end: 1. }` \
+                instead"
+    )]
     pub dot_fadeout_strength: f32,
+    /// Distance from the grid's translation along the vertical axis at which it has faded out.
+    /// Distances of 0 or less are treated as a tiny positive one.
+    pub y_axis_fadeout_distance: f32,
     pub scale: f32,
+    /// Size of a cell along the grid's local X and Z axes, before [`scale`](Self::scale) is
+    /// applied.
+    pub cell_size: Vec2,
+    /// Shifts the cells, in world units, along the grid's local X and Z axes so that lines can be
+    /// snapped to an origin other than the grid's translation. The axis lines stay on the
+    /// translation.
+    pub cell_offset: Vec2,
+    pub subdivision: GridSubdivision,
+    /// Number of minor cells between major lines. Ignored by [`GridSubdivision::Adaptive`],
+    /// which uses its base instead.
+    pub major_line_every: u32,
+    /// Number of major cells between super-major lines, if any. Ignored by
+    /// [`GridSubdivision::Adaptive`].
+    pub super_major_line_every: Option<u32>,
+    /// Unit of the line widths below.
+    pub line_width_mode: LineWidthMode,
+    /// Width of the minor lines, in pixels or world units depending on
+    /// [`line_width_mode`](Self::line_width_mode).
+    pub minor_line_width: f32,
+    /// Width of the major lines, in pixels or world units depending on
+    /// [`line_width_mode`](Self::line_width_mode).
+    pub major_line_width: f32,
+    /// Width of the super-major lines, in pixels or world units depending on
+    /// [`line_width_mode`](Self::line_width_mode).
+    pub super_major_line_width: f32,
+    /// Width of the axis lines, in pixels or world units depending on
+    /// [`line_width_mode`](Self::line_width_mode).
+    pub axis_line_width: f32,
+    pub minor_line_style: GridLineStyle,
+    pub major_line_style: GridLineStyle,
+    /// Clips the grid to a region of its plane. The vertical axis isn't clipped.
+    ///
+    /// Bounded grids are frustum culled once out of view, unless they have [`NoFrustumCulling`],
+    /// which [`InfiniteGridBundle`] still inserts.
+    pub bounds: GridBounds,
+    /// Width, in world units, of the band inside the bounds over which the grid fades out.
+    pub bounds_falloff: f32,
+    /// Color of a line drawn along the bounds, if any.
+    pub border_color: Option<Color>,
+    pub border_line_width: f32,
+    /// Darkens the grid where the lights of the scene are shadowed by other objects, like a
+    /// shadow catcher. Uses the shadow maps of Bevy's lights, and is ignored in prepasses.
+    pub receive_shadows: bool,
+    /// Share of their color the lines lose in full shadow, from 0 to 1.
+    pub shadow_darkening: f32,
+    /// Color painted over the whole plane where it is in shadow, if any, with its alpha scaled by
+    /// how shadowed the plane is.
+    pub shadow_color: Option<Color>,
+    /// How the [`GridCursor`] is drawn.
+    pub cursor: GridCursorStyle,
+    pub alpha_mode: GridAlphaMode,
+    /// Added to the distance from the camera at which a blended grid is sorted against other
+    /// grids and transparent objects. Larger values draw it earlier, behind what is closer, and
+    /// [`f32::INFINITY`] always draws it first.
+    pub sort_bias: f32,
+}
+
+impl InfiniteGridSettings {
+    pub(crate) const DEFAULT_FADE: GridFade = GridFade::Linear {
+        start: 0.,
+        end: 100.,
+    };
+    pub(crate) const DEFAULT_GRAZING_FADE: GridFade = GridFade::Linear {
+        start: 0.75,
+        end: 1.,
+    };
 }
 
 impl Default for InfiniteGridSettings {
+    #[allow(deprecated)]
     fn default() -> Self {
         Self {
+            kind: GridKind::Cartesian,
             x_axis_color: Color::srgb(1.0, 0.2, 0.2),
+            y_axis_color: None,
             z_axis_color: Color::srgb(0.2, 0.2, 1.0),
             minor_line_color: Color::srgb(0.1, 0.1, 0.1),
             major_line_color: Color::srgb(0.25, 0.25, 0.25),
+            super_major_line_color: Color::srgb(0.4, 0.4, 0.4),
+            fill: GridFill::None,
+            fade: Self::DEFAULT_FADE,
+            distance_fog: false,
+            grazing_fade: Self::DEFAULT_GRAZING_FADE,
+            fill_fade: GridFade::None,
             fadeout_distance: 100.,
             dot_fadeout_strength: 0.25,
+            y_axis_fadeout_distance: 100.,
             scale: 1.,
+            cell_size: Vec2::ONE,
+            cell_offset: Vec2::ZERO,
+            subdivision: GridSubdivision::Fixed,
+            major_line_every: 10,
+            super_major_line_every: None,
+            line_width_mode: LineWidthMode::Pixels,
+            minor_line_width: 1.,
+            major_line_width: 1.,
+            super_major_line_width: 1.,
+            axis_line_width: 1.,
+            minor_line_style: GridLineStyle::Solid,
+            major_line_style: GridLineStyle::Solid,
+            bounds: GridBounds::None,
+            bounds_falloff: 0.,
+            border_color: None,
+            border_line_width: 1.,
+            receive_shadows: false,
+            shadow_darkening: 0.5,
+            shadow_color: None,
+            cursor: GridCursorStyle::default(),
+            alpha_mode: GridAlphaMode::Blend,
+            sort_bias: 0.,
+        }
+    }
+}
+
+/// Anchors a grid to true world coordinates in a floating origin world, where everything is
+/// rendered relative to a cell that gets recentered on the camera every so often.
+///
+/// The grid's transform is then taken relative to the global origin, at the corner of cell zero,
+/// instead of the current cell, so that its lines don't jump when the origin shifts. Keep this up
+/// to date with the cell the origin is in.
+///
+/// That transform is only as precise as an `f32` far from the global origin, so keep the grid's
+/// translation near it. The lines still cover the whole plane and stay exact however far the
+/// camera goes, since only the camera's position is combined with the cell in full precision.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct GridFloatingOrigin {
+    /// Cell the render origin currently sits in.
+    pub cell: I64Vec3,
+    /// Edge length of the cells, in world units.
+    pub cell_size: f64,
+}
+
+// The Bundle derive reads every field, including the deprecated ones
+#[allow(deprecated)]
+mod bundle {
+    use super::*;
+
+    /// The components of a grid.
+    ///
+    /// **It inserts [`NoFrustumCulling`], which keeps bounded grids from ever being culled.**
+    /// Remove it after spawning a bounded grid, or spawn [`InfiniteGridSettings`] with
+    /// [`InfiniteGrid`] instead, to let its [`Aabb`] cull it.
+    #[derive(Bundle, Default)]
+    pub struct InfiniteGridBundle {
+        pub transform: Transform,
+        pub global_transform: GlobalTransform,
+        pub settings: InfiniteGridSettings,
+        pub grid: InfiniteGrid,
+        pub visibility: Visibility,
+        pub view_visibility: ViewVisibility,
+        pub inherited_visibility: InheritedVisibility,
+        pub shadow_casters: RenderVisibleEntities,
+        #[deprecated(
+            note = "bounded grids have an Aabb and unbounded ones are never culled, remove \
+                    `NoFrustumCulling` from bounded grids to let them be frustum culled"
+        )]
+        pub no_frustum_culling: NoFrustumCulling,
+        pub sync_to_render_world: SyncToRenderWorld,
+    }
+}
+
+/// Clips a grid to a polygon in its plane, on top of its [`bounds`](InfiniteGridSettings::bounds).
+/// The vertices are in world units along the grid's local X and Z axes from its translation, and
+/// the edges get the bounds' falloff and border.
+///
+/// The vertices are read from a storage buffer, so the polygon is ignored on devices without them
+/// like WebGL2.
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct GridClipPolygon {
+    pub points: Vec<Vec2>,
+}
+
+/// Highlights individual cells of a [`GridKind::Cartesian`] grid, like the hovered or selected
+/// ones, by filling them under the lines and outlining them on top. Other kinds of grids ignore it,
+/// and so do devices without storage buffers like WebGL2.
+///
+/// Cells are indexed like those of a [`GridHeatmap`]. When a cell is listed more than once, the
+/// last highlight wins.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct GridCellHighlights {
+    pub cells: Vec<CellHighlight>,
+    /// Width of the outlines, inside the cells, in the unit of
+    /// [`line_width_mode`](InfiniteGridSettings::line_width_mode).
+    pub outline_width: f32,
+}
+
+impl Default for GridCellHighlights {
+    fn default() -> Self {
+        Self {
+            cells: Vec::new(),
+            outline_width: 2.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CellHighlight {
+    /// The highlighted cell.
+    pub cell: IVec2,
+    /// Drawn over the cell, under the grid lines. Transparent to only outline it.
+    pub fill_color: Color,
+    /// Drawn along the inside of the cell's edges, over the grid lines. Transparent to only fill
+    /// it.
+    pub outline_color: Color,
+}
+
+/// Points at a position on a [`GridKind::Cartesian`] grid, like the one under the mouse, by drawing
+/// the [`cursor`](InfiniteGridSettings::cursor) at the grid line intersection nearest to it. Other
+/// kinds of grids ignore it.
+///
+/// The position is in the same space as the camera's transform, and is projected onto the grid
+/// plane. Nothing is drawn while it's `None`.
+///
+/// It snaps to whole cells, or with [`GridSubdivision::Adaptive`] to the finest lines drawn around
+/// it, which get further apart as the camera moves away.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct GridCursor {
+    pub position: Option<Vec3>,
+}
+
+/// How a [`GridCursor`] is drawn, over everything else on the grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridCursorStyle {
+    pub color: Color,
+    /// How far, in cells, the crosshair drawn along the grid lines through the cursor extends on
+    /// each side of it, if any. [`f32::INFINITY`] makes the lines span the whole grid.
+    pub crosshair_length: Option<f32>,
+    /// Radius, in pixels, of the circle drawn around the cursor, if any.
+    pub circle_radius: Option<f32>,
+    /// Width, in pixels, of the crosshair and the circle. Unlike the grid lines, the cursor keeps
+    /// its size on screen whatever the [`line_width_mode`](InfiniteGridSettings::line_width_mode).
+    pub line_width: f32,
+}
+
+impl Default for GridCursorStyle {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            crosshair_length: Some(1.),
+            circle_radius: Some(6.),
+            line_width: 2.,
+        }
+    }
+}
+
+/// Drapes an image over a grid's plane, on top of its [`fill`](InfiniteGridSettings::fill) and
+/// under its lines, fading out along with the fill.
+///
+/// The image is placed in world units along the grid's local X and Z axes from its translation.
+/// Its top left corner sits on `origin`, its top edge runs along X and its left edge along Z once
+/// turned by `rotation` radians around the grid's normal, like [`Transform::rotate_y`].
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct GridImageOverlay {
+    pub image: Handle<Image>,
+    pub origin: Vec2,
+    pub size: Vec2,
+    pub rotation: f32,
+}
+
+// Bounded grids get an Aabb so that they can be frustum culled, the others are always visible.
+// The vertical axis isn't clipped, so it stretches the Aabb along the normal, through the origin.
+// Cameras with settings that override those of the grids they see are left alone.
+#[allow(clippy::type_complexity)]
+fn update_grid_aabbs(
+    mut commands: Commands,
+    grids: Query<
+        (
+            Entity,
+            Ref<InfiniteGridSettings>,
+            Ref<GlobalTransform>,
+            Option<Ref<GridClipPolygon>>,
+            Option<Ref<GridFloatingOrigin>>,
+        ),
+        (With<InfiniteGrid>, Without<Camera>),
+    >,
+    mut removed_clip_polygons: RemovedComponents<GridClipPolygon>,
+    mut removed_floating_origins: RemovedComponents<GridFloatingOrigin>,
+) {
+    let removed: EntityHashSet = removed_clip_polygons
+        .read()
+        .chain(removed_floating_origins.read())
+        .collect();
+    for (entity, settings, transform, clip_polygon, floating_origin) in &grids {
+        let changed = settings.is_changed()
+            || transform.is_changed()
+            || clip_polygon.as_ref().is_some_and(Ref::is_changed)
+            || floating_origin.as_ref().is_some_and(Ref::is_changed)
+            || removed.contains(&entity);
+        if !changed {
+            continue;
         }
+
+        let bounds = match settings.bounds {
+            GridBounds::None => None,
+            GridBounds::Rect { half_size } => {
+                Some(Rect::from_center_half_size(Vec2::ZERO, half_size))
+            }
+            GridBounds::Circle { radius } => {
+                Some(Rect::from_center_half_size(Vec2::ZERO, Vec2::splat(radius)))
+            }
+        };
+        let polygon = clip_polygon
+            .filter(|clip_polygon| clip_polygon.points.len() >= 3)
+            .map(|clip_polygon| {
+                clip_polygon
+                    .points
+                    .iter()
+                    .fold(Rect::EMPTY, |rect, &point| rect.union_point(point))
+            });
+        let mut region = match (bounds, polygon) {
+            (Some(bounds), Some(polygon)) => bounds.intersect(polygon),
+            (Some(region), None) | (None, Some(region)) => region,
+            (None, None) => {
+                commands.entity(entity).remove::<Aabb>();
+                continue;
+            }
+        };
+        let mut height = 0.;
+        if settings.y_axis_color.is_some() {
+            region = region.union_point(Vec2::ZERO);
+            height = settings.y_axis_fadeout_distance.abs();
+        }
+        // The region is in world units, while the Aabb is scaled along with the transform
+        let scale = transform.scale();
+        // Culling happens where things are rendered, relative to the floating origin's cell, so
+        // the Aabb moves back by the cell's offset from the true world origin, as in GridPlane
+        let origin_shift = floating_origin.map_or(Vec3::ZERO, |floating_origin| {
+            (floating_origin.cell.as_dvec3() * floating_origin.cell_size).as_vec3()
+        });
+        let shift = transform.affine().inverse().transform_vector3(origin_shift);
+        commands.entity(entity).insert(Aabb::from_min_max(
+            Vec3::new(region.min.x, -height, region.min.y) / scale - shift,
+            Vec3::new(region.max.x, height, region.max.y) / scale - shift,
+        ));
     }
 }
 
-#[derive(Bundle, Default)]
-pub struct InfiniteGridBundle {
-    pub transform: Transform,
-    pub global_transform: GlobalTransform,
-    pub settings: InfiniteGridSettings,
-    pub grid: InfiniteGrid,
-    pub visibility: Visibility,
-    pub view_visibility: ViewVisibility,
-    pub inherited_visibility: InheritedVisibility,
-    pub shadow_casters: RenderVisibleEntities,
-    pub no_frustum_culling: NoFrustumCulling,
-    pub sync_to_render_world: SyncToRenderWorld,
+/// The pattern of lines drawn on the grid plane.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GridKind {
+    /// Square cells along the grid's local X and Z axes.
+    #[default]
+    Cartesian,
+    /// Concentric rings `ring_spacing` world units apart and `spoke_count` evenly spaced spokes,
+    /// centered on the grid's translation. The major lines are every
+    /// [`major_line_every`](InfiniteGridSettings::major_line_every) rings and spokes, and the
+    /// cell size and offset are ignored. Spacings of 0 or less are treated as a tiny positive one,
+    /// and a spoke count of 0 as 1.
+    Polar { ring_spacing: f32, spoke_count: u32 },
+    /// Hexagons one cell across their flats, with one centered on the grid's translation. Major
+    /// lines, line styles and adaptive subdivision don't apply.
+    Hexagonal { orientation: HexOrientation },
+    /// Equilateral triangles with sides one cell long and a vertex on the grid's translation. Major
+    /// lines, line styles and adaptive subdivision don't apply.
+    Triangular,
+}
+
+/// Which way the hexagons of [`GridKind::Hexagonal`] point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HexOrientation {
+    /// A vertex points along the grid's local Z axis, and rows of hexagons run along X.
+    #[default]
+    PointyTop,
+    /// A vertex points along the grid's local X axis, and columns of hexagons run along Z.
+    FlatTop,
+}
+
+/// What a grid draws behind its lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GridFill {
+    #[default]
+    None,
+    /// A single color.
+    Solid(Color),
+    /// Two colors alternating from one cell to the next. Hexagons can't be told apart with two
+    /// colors, so hexagonal grids only use the first one, and polar grids with an odd number of
+    /// spokes have two neighbouring sectors of the same color.
+    Checkerboard(Color, Color),
+}
+
+/// How the grid's opacity falls off as some quantity grows, see [`InfiniteGridSettings::fade`]
+/// and [`InfiniteGridSettings::grazing_fade`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GridFade {
+    #[default]
+    None,
+    /// Fades out linearly from `start` to `end`.
+    Linear { start: f32, end: f32 },
+    /// An opacity of `exp(-density * x)`, which never quite reaches 0.
+    Exponential { density: f32 },
+    /// An opacity of `exp(-(density * x)²)`, which stays opaque for longer and then falls off
+    /// faster.
+    ExponentialSquared { density: f32 },
+    /// Fades out from `start` to `end` along a smoothstep, easing in and out of both ends.
+    Smoothstep { start: f32, end: f32 },
+}
+
+/// How the grid picks which lines to draw as the camera zooms in and out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GridSubdivision {
+    /// Minor lines every cell and major lines every
+    /// [`major_line_every`](InfiniteGridSettings::major_line_every) cells, regardless of zoom.
+    #[default]
+    Fixed,
+    /// Show the powers of `base` that are legible at the current zoom level, cross-fading
+    /// between them so that lines never get denser than a few pixels apart.
+    Adaptive { base: u32 },
+}
+
+/// Unit in which the line widths of [`InfiniteGridSettings`] are expressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineWidthMode {
+    /// Lines keep the same width on screen however far away they are.
+    #[default]
+    Pixels,
+    /// Lines have a physical width in world units, like floor markings, and get thinner with
+    /// distance until they fade into the cells.
+    World,
+}
+
+/// How the lines of a tier are drawn. Super-major and axis lines are always solid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GridLineStyle {
+    #[default]
+    Solid,
+    /// A dot at each intersection, as wide as the line.
+    Dotted,
+    /// A small plus at each intersection.
+    Crosshair,
+    /// Dashes centered on the intersections.
+    Dashed,
+}
+
+/// How the grid is combined with the rest of the scene.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GridAlphaMode {
+    /// Blended over the scene after the opaque geometry, without writing depth.
+    #[default]
+    Blend,
+    /// Drawn with the opaque geometry. Pixels whose alpha is at least the given threshold are
+    /// written fully opaque along with their depth, and the others are discarded, so the grid
+    /// occludes what is behind it and shows up in the depth, normal and motion vector prepasses.
+    /// A threshold of 0 or less turns the whole plane, up to the horizon, into an opaque ground.
+    /// Cameras with a deferred prepass only get the grid in their main pass.
+    Mask(f32),
+}
+
+/// Region of its plane a grid is clipped to, centered on the grid's translation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GridBounds {
+    #[default]
+    None,
+    /// A rectangle extending `half_size` world units along the grid's local X and Z axes.
+    Rect { half_size: Vec2 },
+    /// A disc with a radius of `radius` world units.
+    Circle { radius: f32 },
 }
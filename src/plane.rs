@@ -0,0 +1,471 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+    ecs::system::SystemParam,
+    math::{DVec2, DVec3},
+    prelude::*,
+};
+
+use crate::{
+    lattice::{hex_cell, triangle_cell},
+    GridFloatingOrigin, GridKind, GridSubdivision, InfiniteGridSettings, TriangleCell,
+};
+
+/// Looks up the grids of the world to do the same math on them as the shader, e.g. to pick cells
+/// or snap points under the mouse.
+#[derive(SystemParam)]
+pub struct InfiniteGrids<'w, 's> {
+    grids: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static InfiniteGridSettings,
+            &'static GlobalTransform,
+            Option<&'static GridFloatingOrigin>,
+        ),
+        Without<Camera>,
+    >,
+    cameras: Query<'w, 's, &'static InfiniteGridSettings, With<Camera>>,
+}
+
+impl InfiniteGrids<'_, '_> {
+    /// The plane of the grid on `entity`, or `None` if it isn't a grid.
+    ///
+    /// It uses the grid's own settings. Use [`get_seen_by`](Self::get_seen_by) for the settings a
+    /// camera overrides them with.
+    pub fn get(&self, entity: Entity) -> Option<GridPlane<'_>> {
+        let (_, settings, transform, floating_origin) = self.grids.get(entity).ok()?;
+        Some(GridPlane::new(settings, transform, floating_origin))
+    }
+
+    /// The plane of the grid on `entity` as `camera` draws it, with the [`InfiniteGridSettings`]
+    /// of the camera instead of the grid's if it has some. `None` if `entity` isn't a grid.
+    pub fn get_seen_by(&self, entity: Entity, camera: Entity) -> Option<GridPlane<'_>> {
+        let (_, settings, transform, floating_origin) = self.grids.get(entity).ok()?;
+        let settings = self.cameras.get(camera).unwrap_or(settings);
+        Some(GridPlane::new(settings, transform, floating_origin))
+    }
+
+    /// The planes of all grids, with their own settings like [`get`](Self::get).
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, GridPlane<'_>)> {
+        self.grids
+            .iter()
+            .map(|(entity, settings, transform, floating_origin)| {
+                (entity, GridPlane::new(settings, transform, floating_origin))
+            })
+    }
+}
+
+/// Which lines of a grid to snap to.
+///
+/// With [`GridSubdivision::Adaptive`] these are always the lines one and `base` cells apart,
+/// whichever powers of `base` the shader draws at the camera's current zoom, so zoomed out grids
+/// may snap to lines too dense to be drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GridLineTier {
+    /// Every line, one cell apart.
+    Minor,
+    /// Every [`major_line_every`](InfiniteGridSettings::major_line_every) lines, or every `base`
+    /// lines with [`GridSubdivision::Adaptive`].
+    Major,
+}
+
+/// A cell of a grid, as found by [`GridPlane::world_to_cell`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GridCell {
+    /// Column along X and row along Z of a [`GridKind::Cartesian`] cell, counted from the
+    /// [`cell_offset`](InfiniteGridSettings::cell_offset) like the cells of a
+    /// [`GridHeatmap`](crate::GridHeatmap).
+    Square(IVec2),
+    /// Ring from the origin and sector from the +X axis towards +Z of a [`GridKind::Polar`] cell,
+    /// the first sector being the one right after the axis.
+    Polar(IVec2),
+    /// Axial coordinates of a [`GridKind::Hexagonal`] cell, following the convention of
+    /// [`InfiniteGridSettings::world_to_hex_cell`].
+    Hex(IVec2),
+    Triangle(TriangleCell),
+}
+
+/// The plane of a grid and its cells, as drawn by the shader with [`settings`](Self::settings).
+///
+/// World positions are the ones things are rendered at, so they are relative to the current cell
+/// when the grid has a [`GridFloatingOrigin`]. Plane positions are in world units along the
+/// grid's local X and Z axes from its translation, like the points of a
+/// [`GridClipPolygon`](crate::GridClipPolygon).
+///
+/// Cell coordinates and snapping are only defined for [`GridKind::Cartesian`] and
+/// [`GridKind::Polar`] grids, while [`world_to_cell`](Self::world_to_cell) finds the cells of every
+/// kind.
+#[derive(Clone, Copy, Debug)]
+pub struct GridPlane<'a> {
+    pub settings: &'a InfiniteGridSettings,
+    pub(crate) rotation: Quat,
+    // Translation of the grid in the space things are rendered in
+    pub(crate) origin: DVec3,
+}
+
+impl<'a> GridPlane<'a> {
+    pub(crate) fn new(
+        settings: &'a InfiniteGridSettings,
+        transform: &GlobalTransform,
+        floating_origin: Option<&GridFloatingOrigin>,
+    ) -> Self {
+        let origin_shift = floating_origin.map_or(DVec3::ZERO, |floating_origin| {
+            floating_origin.cell.as_dvec3() * floating_origin.cell_size
+        });
+        Self {
+            settings,
+            rotation: transform.compute_transform().rotation,
+            origin: transform.translation().as_dvec3() - origin_shift,
+        }
+    }
+
+    /// The grid's local Y axis, which its plane faces.
+    pub fn normal(&self) -> Dir3 {
+        Dir3::new_unchecked(self.rotation * Vec3::Y)
+    }
+
+    /// Position of `world_position` projected onto the plane.
+    pub fn world_to_plane(&self, world_position: Vec3) -> Vec2 {
+        self.world_to_plane_f64(world_position.as_dvec3()).as_vec2()
+    }
+
+    // Done in f64 so that far away grids of a floating origin world stay precise, and so that the
+    // shader's phases stay exact when the camera is far from the grid's translation
+    pub(crate) fn world_to_plane_f64(&self, world_position: DVec3) -> DVec2 {
+        (self.rotation.as_dquat().inverse() * (world_position - self.origin)).xz()
+    }
+
+    // Counted in cells along X and Z from the cell offset, which are the cells of Cartesian grids
+    // and the lattice the hexagons and triangles of the other kinds are laid out on
+    pub(crate) fn plane_to_lattice(&self, plane_position: DVec2) -> DVec2 {
+        let scale = (self.settings.scale / self.settings.cell_size).as_dvec2();
+        (plane_position - self.settings.cell_offset.as_dvec2()) * scale
+    }
+
+    /// World position of a point of the plane.
+    pub fn plane_to_world(&self, plane_position: Vec2) -> Vec3 {
+        let offset = self.rotation * Vec3::new(plane_position.x, 0., plane_position.y);
+        (self.origin + offset.as_dvec3()).as_vec3()
+    }
+
+    /// Position of `world_position` projected onto the plane, counted in cells like the shader
+    /// does: along the X and Z axes from the
+    /// [`cell_offset`](InfiniteGridSettings::cell_offset) for Cartesian grids, and in rings and
+    /// spokes from the +X axis towards +Z for polar ones.
+    pub fn world_to_cell_coords(&self, world_position: Vec3) -> Option<Vec2> {
+        let plane_position = self.world_to_plane_f64(world_position.as_dvec3());
+        match self.settings.kind {
+            GridKind::Cartesian => Some(self.plane_to_lattice(plane_position).as_vec2()),
+            GridKind::Polar {
+                ring_spacing,
+                spoke_count,
+            } => Some(polar_cell_coords(
+                plane_position.as_vec2(),
+                ring_spacing,
+                spoke_count,
+            )),
+            _ => None,
+        }
+    }
+
+    /// World position of a point counted in cells, the inverse of
+    /// [`world_to_cell_coords`](Self::world_to_cell_coords).
+    pub fn cell_coords_to_world(&self, cell_coords: Vec2) -> Option<Vec3> {
+        let plane_position = match self.settings.kind {
+            GridKind::Cartesian => {
+                cell_coords * self.settings.cell_size / self.settings.scale
+                    + self.settings.cell_offset
+            }
+            GridKind::Polar {
+                ring_spacing,
+                spoke_count,
+            } => {
+                Vec2::from_angle(cell_coords.y * TAU / spoke_count.max(1) as f32)
+                    * cell_coords.x
+                    * ring_spacing.max(f32::EPSILON)
+            }
+            _ => return None,
+        };
+        Some(self.plane_to_world(plane_position))
+    }
+
+    /// The cell that contains `world_position` once projected onto the plane.
+    pub fn world_to_cell(&self, world_position: Vec3) -> GridCell {
+        let plane_position = self.world_to_plane_f64(world_position.as_dvec3());
+        let lattice = || self.plane_to_lattice(plane_position).as_vec2();
+        match self.settings.kind {
+            GridKind::Cartesian => GridCell::Square(lattice().floor().as_ivec2()),
+            GridKind::Polar {
+                ring_spacing,
+                spoke_count,
+            } => {
+                let cell = polar_cell_coords(plane_position.as_vec2(), ring_spacing, spoke_count)
+                    .floor()
+                    .as_ivec2();
+                GridCell::Polar(cell.with_y(cell.y.rem_euclid(spoke_count.max(1) as i32)))
+            }
+            GridKind::Hexagonal { orientation } => GridCell::Hex(hex_cell(orientation, lattice())),
+            GridKind::Triangular => GridCell::Triangle(triangle_cell(lattice())),
+        }
+    }
+
+    /// World transform of a cell of a Cartesian grid: on its center, rotated like the grid and
+    /// scaled to its size along X and Z, so that a unit mesh under it covers the cell.
+    pub fn cell_transform(&self, cell: IVec2) -> Option<Transform> {
+        if self.settings.kind != GridKind::Cartesian {
+            return None;
+        }
+        let size = self.settings.cell_size / self.settings.scale;
+        Some(Transform {
+            translation: self.cell_coords_to_world(cell.as_vec2() + 0.5)?,
+            rotation: self.rotation,
+            scale: Vec3::new(size.x, 1., size.y),
+        })
+    }
+
+    /// World position of the intersection of `tier` lines nearest to `world_position`, which for
+    /// minor lines of a Cartesian grid is where its [`GridCursor`](crate::GridCursor) is drawn.
+    pub fn snap_to_intersection(&self, world_position: Vec3, tier: GridLineTier) -> Option<Vec3> {
+        let spacing = self.line_spacing(tier);
+        let cell_coords = self.world_to_cell_coords(world_position)?;
+        self.cell_coords_to_world((cell_coords / spacing).round() * spacing)
+    }
+
+    /// World position of the point on the `tier` line nearest to `world_position`.
+    pub fn snap_to_line(&self, world_position: Vec3, tier: GridLineTier) -> Option<Vec3> {
+        let spacing = self.line_spacing(tier);
+        let cell_coords = self.world_to_cell_coords(world_position)?;
+        let snapped = (cell_coords / spacing).round() * spacing;
+        let on_ring_or_column = cell_coords.with_x(snapped.x);
+        let on_spoke_or_row = match self.settings.kind {
+            // Dropped perpendicularly onto the spoke, which ends at the origin
+            GridKind::Polar { spoke_count, .. } => {
+                let angle = (cell_coords.y - snapped.y) * TAU / spoke_count.max(1) as f32;
+                Vec2::new(cell_coords.x * angle.cos().max(0.), snapped.y)
+            }
+            _ => cell_coords.with_y(snapped.y),
+        };
+        let on_ring_or_column = self.cell_coords_to_world(on_ring_or_column)?;
+        let on_spoke_or_row = self.cell_coords_to_world(on_spoke_or_row)?;
+        // Both are on the plane, so the offset along its normal is the same for each
+        Some(
+            if world_position.distance_squared(on_ring_or_column)
+                <= world_position.distance_squared(on_spoke_or_row)
+            {
+                on_ring_or_column
+            } else {
+                on_spoke_or_row
+            },
+        )
+    }
+
+    /// Spacing of `tier` lines, in cells, regardless of the zoom level.
+    fn line_spacing(&self, tier: GridLineTier) -> f32 {
+        match (tier, self.settings.subdivision) {
+            (GridLineTier::Minor, _) => 1.,
+            (GridLineTier::Major, GridSubdivision::Fixed) => {
+                self.settings.major_line_every.max(1) as f32
+            }
+            (GridLineTier::Major, GridSubdivision::Adaptive { base }) => base.max(2) as f32,
+        }
+    }
+}
+
+// Rings and spokes from the +X axis towards +Z, with the same guards as the shader
+fn polar_cell_coords(plane_position: Vec2, ring_spacing: f32, spoke_count: u32) -> Vec2 {
+    Vec2::new(
+        plane_position.length() / ring_spacing.max(f32::EPSILON),
+        plane_position.to_angle() * spoke_count.max(1) as f32 / TAU,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::I64Vec3;
+
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+    use crate::HexOrientation;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.distance(b) < 1e-4, "{a} != {b}");
+    }
+
+    fn transform() -> GlobalTransform {
+        GlobalTransform::from(
+            Transform::from_xyz(3., 2., -5.).with_rotation(Quat::from_rotation_y(0.7)),
+        )
+    }
+
+    fn assert_round_trips(plane: GridPlane, points: &[Vec2]) {
+        for &point in points {
+            let world = plane.plane_to_world(point);
+            let cell_coords = plane.world_to_cell_coords(world).unwrap();
+            assert_near(plane.cell_coords_to_world(cell_coords).unwrap(), world);
+        }
+    }
+
+    const POINTS: [Vec2; 4] = [
+        Vec2::new(0.3, 0.2),
+        Vec2::new(-7.5, 2.25),
+        Vec2::new(12., -4.8),
+        Vec2::new(-0.1, -9.),
+    ];
+
+    #[test]
+    fn cartesian_round_trips() {
+        let settings = InfiniteGridSettings {
+            scale: 2.,
+            cell_size: Vec2::new(1., 3.),
+            ..default()
+        };
+        let transform = transform();
+        let plane = GridPlane::new(&settings, &transform, None);
+        assert_round_trips(plane, &POINTS);
+        let cell_coords = plane.world_to_cell_coords(plane.plane_to_world(Vec2::new(1., 3.)));
+        assert!(cell_coords.unwrap().abs_diff_eq(Vec2::new(2., 2.), 1e-5));
+    }
+
+    #[test]
+    fn cell_offset_round_trips() {
+        let settings = InfiniteGridSettings {
+            cell_offset: Vec2::new(0.5, -0.25),
+            ..default()
+        };
+        let transform = transform();
+        let plane = GridPlane::new(&settings, &transform, None);
+        assert_round_trips(plane, &POINTS);
+        // The cell (0, 0) starts on the offset
+        let world = plane.plane_to_world(Vec2::new(0.6, -0.2));
+        assert_eq!(plane.world_to_cell(world), GridCell::Square(IVec2::ZERO));
+        let world = plane.plane_to_world(Vec2::new(0.4, -0.2));
+        assert_eq!(
+            plane.world_to_cell(world),
+            GridCell::Square(IVec2::new(-1, 0))
+        );
+    }
+
+    #[test]
+    fn floating_origin_round_trips() {
+        let settings = InfiniteGridSettings {
+            cell_offset: Vec2::new(0.5, 0.),
+            ..default()
+        };
+        let transform = GlobalTransform::from_xyz(1., 0., 0.);
+        // The render origin sits in the cell 1000 world units along X
+        let floating_origin = GridFloatingOrigin {
+            cell: I64Vec3::new(10, 0, 0),
+            cell_size: 100.,
+        };
+        let plane = GridPlane::new(&settings, &transform, Some(&floating_origin));
+        assert_round_trips(plane, &POINTS);
+        let cell_coords = plane.world_to_cell_coords(Vec3::new(0.25, 0., 0.75));
+        assert_eq!(cell_coords, Some(Vec2::new(998.75, 0.75)));
+        assert_near(
+            plane.plane_to_world(Vec2::new(999.5, 0.)),
+            Vec3::new(0.5, 0., 0.),
+        );
+
+        let settings = InfiniteGridSettings {
+            kind: GridKind::Hexagonal {
+                orientation: HexOrientation::PointyTop,
+            },
+            ..default()
+        };
+        let plane = GridPlane::new(&settings, &transform, Some(&floating_origin));
+        let cell = plane.world_to_cell(Vec3::new(2., 0., 0.));
+        assert_eq!(cell, GridCell::Hex(IVec2::new(1001, 0)));
+    }
+
+    #[test]
+    fn polar_round_trips() {
+        let settings = InfiniteGridSettings {
+            kind: GridKind::Polar {
+                ring_spacing: 1.5,
+                spoke_count: 12,
+            },
+            ..default()
+        };
+        let transform = transform();
+        let plane = GridPlane::new(&settings, &transform, None);
+        assert_round_trips(plane, &POINTS);
+        let cell = plane.world_to_cell(plane.plane_to_world(Vec2::new(0., -2.)));
+        assert_eq!(cell, GridCell::Polar(IVec2::new(1, 9)));
+    }
+
+    #[test]
+    fn polar_snap_to_line() {
+        let settings = InfiniteGridSettings {
+            kind: GridKind::Polar {
+                ring_spacing: 1.,
+                spoke_count: 4,
+            },
+            ..default()
+        };
+        let plane = GridPlane::new(&settings, &GlobalTransform::IDENTITY, None);
+        let snap = |x, z| {
+            plane
+                .snap_to_line(Vec3::new(x, 1., z), GridLineTier::Minor)
+                .unwrap()
+        };
+        // Dropped perpendicularly onto the nearest spoke
+        assert_near(snap(2.5, 0.3), Vec3::new(2.5, 0., 0.));
+        assert_near(snap(-0.2, 3.5), Vec3::new(0., 0., 3.5));
+        // Onto the nearest ring, along the ray from the origin
+        let point = Vec2::new(1.9, 1.);
+        let on_ring = point.normalize() * 2.;
+        assert_near(snap(point.x, point.y), Vec3::new(on_ring.x, 0., on_ring.y));
+    }
+
+    #[test]
+    fn polar_zero_ring_spacing() {
+        let settings = InfiniteGridSettings {
+            kind: GridKind::Polar {
+                ring_spacing: 0.,
+                spoke_count: 0,
+            },
+            ..default()
+        };
+        let plane = GridPlane::new(&settings, &GlobalTransform::IDENTITY, None);
+        let cell_coords = plane.world_to_cell_coords(Vec3::new(1., 0., 0.)).unwrap();
+        assert!(cell_coords.is_finite());
+    }
+
+    #[test]
+    fn camera_settings_override_the_grid() {
+        let mut world = World::new();
+        let grid = world
+            .spawn((InfiniteGridSettings::default(), GlobalTransform::IDENTITY))
+            .id();
+        let camera = world
+            .spawn((
+                Camera::default(),
+                InfiniteGridSettings {
+                    scale: 2.,
+                    ..default()
+                },
+                GlobalTransform::IDENTITY,
+            ))
+            .id();
+        let other_camera = world.spawn(Camera::default()).id();
+        let mut state = SystemState::<InfiniteGrids>::new(&mut world);
+        let grids = state.get(&world);
+
+        let point = Vec3::new(1.5, 0., 1.5);
+        let cell_coords = |plane: GridPlane| plane.world_to_cell_coords(point).unwrap();
+        assert_eq!(cell_coords(grids.get(grid).unwrap()), Vec2::splat(1.5));
+        assert_eq!(
+            cell_coords(grids.get_seen_by(grid, camera).unwrap()),
+            Vec2::splat(3.)
+        );
+        assert_eq!(
+            cell_coords(grids.get_seen_by(grid, other_camera).unwrap()),
+            Vec2::splat(1.5)
+        );
+        assert!(grids.get(camera).is_none());
+        assert_eq!(grids.iter().count(), 1);
+    }
+}
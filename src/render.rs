@@ -2,37 +2,65 @@ use std::borrow::Cow;
 
 use bevy::{
     asset::{load_internal_asset, uuid_handle},
-    core_pipeline::core_3d::Transparent3d,
+    core_pipeline::{
+        core_3d::{AlphaMask3d, Transparent3d},
+        oit::OrderIndependentTransparencySettings,
+        prepass::{
+            prepass_target_descriptors, AlphaMask3dPrepass, DeferredPrepass, MotionVectorPrepass,
+            NormalPrepass, OpaqueNoLightmap3dBatchSetKey, OpaqueNoLightmap3dBinKey,
+            PreviousViewData, ViewPrepassTextures,
+        },
+    },
     ecs::{
+        entity::EntityHashMap,
         query::ROQueryItem,
-        system::lifetimeless::{Read, SRes},
-        system::SystemParamItem,
+        system::lifetimeless::Read,
+        system::{SystemChangeTick, SystemParamItem},
     },
     image::BevyDefault,
-    pbr::MeshPipelineKey,
+    light::ShadowFilteringMethod,
+    math::{DVec2, DVec3},
+    pbr::{
+        ExtractedAtmosphere, MeshPipeline, MeshPipelineKey, MeshPipelineViewLayoutKey,
+        SetMeshViewBindGroup, SetMeshViewBindingArrayBindGroup,
+    },
     prelude::*,
     render::{
+        camera::ExtractedCamera,
+        mesh::allocator::SlabId,
+        render_asset::RenderAssets,
         render_phase::{
-            AddRenderCommand, DrawFunctions, PhaseItem, PhaseItemExtraIndex, RenderCommand,
-            RenderCommandResult, SetItemPipeline, ViewSortedRenderPhases,
+            AddRenderCommand, BinnedRenderPhaseType, DrawFunctionId, DrawFunctions,
+            InputUniformIndex, PhaseItem, PhaseItemExtraIndex, RenderCommand, RenderCommandResult,
+            SetItemPipeline, ViewBinnedRenderPhases, ViewSortedRenderPhases,
         },
         render_resource::PrimitiveTopology,
         render_resource::{
-            binding_types::uniform_buffer, BindGroup, BindGroupEntries, BindGroupLayoutDescriptor,
-            BindGroupLayoutEntries, BlendState, ColorTargetState, ColorWrites, CompareFunction,
-            DepthBiasState, DepthStencilState, DynamicUniformBuffer, FragmentState,
-            MultisampleState, PipelineCache, PolygonMode, PrimitiveState, RenderPipelineDescriptor,
-            ShaderStages, ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines,
-            StencilFaceState, StencilState, TextureFormat, VertexState,
+            binding_types::{sampler, storage_buffer_read_only, texture_2d, uniform_buffer},
+            BindGroup, BindGroupEntries, BindGroupEntry, BindGroupLayoutDescriptor,
+            BindGroupLayoutEntries, BlendState, BufferId, CachedRenderPipelineId, ColorTargetState,
+            ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, DynamicUniformBuffer,
+            FragmentState, IntoBinding, MultisampleState, PipelineCache, PolygonMode,
+            PrimitiveState, RenderPipelineDescriptor, SamplerBindingType, SamplerId, ShaderStages,
+            ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, StencilFaceState,
+            StencilState, StorageBuffer, TextureFormat, TextureSampleType, TextureViewId,
+            VertexState,
         },
         renderer::{RenderDevice, RenderQueue},
         sync_world::RenderEntity,
+        texture::{FallbackImage, GpuImage},
         view::{ExtractedView, RenderVisibleEntities, ViewTarget},
         Extract, ExtractSchedule, Render, RenderApp, RenderSystems,
     },
+    shader::ShaderDefVal,
 };
 
-use crate::InfiniteGridSettings;
+use crate::{
+    heatmap::PackedHeatmapCells, CellHighlight, GridAlphaMode, GridBounds, GridCellHighlights,
+    GridClipPolygon, GridCursor, GridFade, GridFill, GridFloatingOrigin, GridHeatmap,
+    GridImageOverlay, GridKind, GridLineStyle, GridPlane, GridSubdivision, HeatmapValues,
+    HexOrientation, InfiniteGridSettings, LineWidthMode,
+};
 
 const GRID_SHADER_HANDLE: Handle<Shader> = uuid_handle!("01968ec1-1753-7731-9b47-b50296bcb86b");
 
@@ -46,9 +74,16 @@ pub fn render_app_builder(app: &mut App) {
         .init_resource::<GridViewUniforms>()
         .init_resource::<InfiniteGridUniforms>()
         .init_resource::<GridDisplaySettingsUniforms>()
+        .init_resource::<GridClipPolygons>()
+        .init_resource::<GridHeatmapRamps>()
+        .init_resource::<GridCellHighlightBuffer>()
         .init_resource::<InfiniteGridPipeline>()
         .init_resource::<SpecializedRenderPipelines<InfiniteGridPipeline>>()
         .add_render_command::<Transparent3d, DrawInfiniteGrid>()
+        .add_render_command::<Transparent3d, DrawShadowedInfiniteGrid>()
+        .add_render_command::<AlphaMask3d, DrawInfiniteGrid>()
+        .add_render_command::<AlphaMask3d, DrawShadowedInfiniteGrid>()
+        .add_render_command::<AlphaMask3dPrepass, DrawInfiniteGrid>()
         .add_systems(
             ExtractSchedule,
             (extract_infinite_grids, extract_per_camera_settings),
@@ -73,41 +108,570 @@ pub fn render_app_builder(app: &mut App) {
 struct ExtractedInfiniteGrid {
     transform: GlobalTransform,
     grid: InfiniteGridSettings,
+    floating_origin: Option<GridFloatingOrigin>,
+    clip_polygon: Option<GridClipPolygon>,
+    overlay: Option<GridImageOverlay>,
+    heatmap: Option<ExtractedHeatmap>,
+    highlights: Option<GridCellHighlights>,
+    cursor: Option<Vec3>,
+}
+
+struct ExtractedHeatmap {
+    image: Handle<Image>,
+    // Cell of the image's first texel
+    origin: IVec2,
+    ramp: Vec<Vec4>,
+    range: Vec2,
 }
 
+impl ExtractedInfiniteGrid {
+    // With a floating origin the grid's transform is in true world coordinates, while the camera's
+    // is relative to the cell the render origin currently sits in
+    fn origin_shift(&self) -> DVec3 {
+        self.floating_origin.map_or(DVec3::ZERO, |floating_origin| {
+            floating_origin.cell.as_dvec3() * floating_origin.cell_size
+        })
+    }
+
+    // Storage buffers aren't available everywhere, e.g. on WebGL2, so the parts of the grid that
+    // read them are only bound and compiled in when the grid uses them and the device has them
+    fn features(&self, storage_buffers: bool) -> GridFeaturesKey {
+        GridFeaturesKey {
+            // Anything smaller than a triangle clips nothing
+            clip_polygon: storage_buffers
+                && self
+                    .clip_polygon
+                    .as_ref()
+                    .is_some_and(|clip_polygon| clip_polygon.points.len() >= 3),
+            heatmap: storage_buffers && self.heatmap.is_some(),
+            cell_highlights: storage_buffers
+                && self
+                    .highlights
+                    .as_ref()
+                    .is_some_and(|highlights| !highlights.cells.is_empty()),
+        }
+    }
+}
+
+// Everything is relative to the camera, one per grid and per view, so that the shader never deals
+// with large world coordinates
 #[derive(Debug, ShaderType)]
 pub struct InfiniteGridUniform {
     rot_matrix: Mat3,
+    // Grid origin relative to the camera
     offset: Vec3,
     normal: Vec3,
+    // Camera position in cells, reduced modulo the period after which the lines repeat
+    cell_phase: Vec2,
+    // Same for the lines further apart than cell_period, reduced modulo a larger period
+    coarse_cell_phase: Vec2,
+    cell_period: f32,
+    // Range of the grid's clip polygon in the clip polygon buffer, empty when it has none
+    clip_polygon_start: u32,
+    clip_polygon_len: u32,
+    // Maps plane coordinates relative to overlay_origin to the UVs of the overlay image
+    overlay_from_plane: Mat2,
+    // Overlay origin relative to the camera's projection on the plane
+    overlay_origin: Vec2,
+    // 1 when the grid has an overlay whose image is loaded
+    overlay: u32,
+    // Added to the cells of the fragments to get their actual coordinates
+    cell_shift: IVec2,
+    // Cell of the heatmap image's first texel
+    heatmap_origin: IVec2,
+    heatmap_range: Vec2,
+    // Range of the heatmap's colors in the heatmap ramp buffer, empty when it has none
+    heatmap_ramp_start: u32,
+    heatmap_ramp_len: u32,
+    // Range of the grid's highlights in the cell highlight buffer, empty when it has none
+    cell_highlight_start: u32,
+    cell_highlight_len: u32,
+    cell_highlight_outline_width: f32,
+    // Intersection the cursor snapped to, in the same cells as the fragments
+    cursor_coord: Vec2,
+    // 1 when the grid has a cursor
+    cursor: u32,
+}
+
+// Sorted by row and then column within each grid
+#[derive(Clone, ShaderType)]
+struct GpuCellHighlight {
+    cell: IVec2,
+    fill_color: Vec4,
+    outline_color: Vec4,
+}
+
+/// Highlights sorted by row and then column like the shader looks them up, keeping only the last
+/// highlight of each cell.
+fn sorted_cell_highlights(highlights: &[CellHighlight]) -> Vec<&CellHighlight> {
+    let mut cells: Vec<_> = highlights.iter().rev().collect();
+    // Stable, so that the last highlight of each cell comes first and is kept
+    cells.sort_by_key(|highlight| (highlight.cell.y, highlight.cell.x));
+    cells.dedup_by_key(|highlight| highlight.cell);
+    cells
+}
+
+impl InfiniteGridUniform {
+    fn new(
+        grid: &ExtractedInfiniteGrid,
+        settings: &InfiniteGridSettings,
+        view: &ExtractedView,
+        clip_polygon: UVec2,
+        overlay: Option<&GridImageOverlay>,
+        heatmap: Option<(&ExtractedHeatmap, UVec2)>,
+        highlights: Option<(&GridCellHighlights, UVec2)>,
+    ) -> Self {
+        let plane = GridPlane::new(settings, &grid.transform, grid.floating_origin.as_ref());
+        let normal = *plane.normal();
+        let camera = view.world_from_view.translation().as_dvec3();
+
+        // Done in f64 so that the phase stays exact when the camera is far away from the origin
+        let camera_plane = plane.world_to_plane_f64(camera);
+        let camera_cells = plane.plane_to_lattice(camera_plane);
+
+        let view_distance = if view.clip_from_view.w_axis.w == 1.0 {
+            let extent = Vec2::new(view.clip_from_view.x_axis.x, view.clip_from_view.y_axis.y);
+            extent.recip().max_element() as f64
+        } else {
+            (camera - plane.origin).dot(normal.as_dvec3()).abs()
+        };
+        let scale = settings.scale / settings.cell_size;
+        let periods = cell_periods(settings, view_distance * scale.max_element() as f64);
+        let cartesian = settings.kind == GridKind::Cartesian;
+        let CameraPhase {
+            cell_phase,
+            coarse_cell_phase,
+            cell_period,
+            cell_shift,
+        } = CameraPhase::new(camera_cells, periods, cartesian);
+        let (heatmap_origin, heatmap_range, heatmap_ramp) = match heatmap.filter(|_| cartesian) {
+            Some((heatmap, ramp)) => (heatmap.origin, heatmap.range, ramp),
+            None => (IVec2::ZERO, Vec2::ZERO, UVec2::ZERO),
+        };
+        let (cell_highlight_outline_width, cell_highlights) = match highlights.filter(|_| cartesian)
+        {
+            Some((highlights, range)) => (highlights.outline_width.max(0.), range),
+            None => (0., UVec2::ZERO),
+        };
+        // Snapped while its offset from the camera is exact, then moved to the phase like the
+        // camera
+        let cursor_coord = grid.cursor.filter(|_| cartesian).map(|cursor| {
+            let cursor_plane = plane.world_to_plane_f64(cursor.as_dvec3());
+            let spacing = cursor_spacing(settings, &plane, view, cursor - camera.as_vec3());
+            let cursor_cells = (plane.plane_to_lattice(cursor_plane) / spacing).round() * spacing;
+            (cursor_cells - camera_cells).as_vec2() + cell_phase
+        });
+
+        // The rows are the image's edges turned like the X and Z axes by a rotation around Y
+        let overlay_from_plane = overlay.map_or(Mat2::ZERO, |overlay| {
+            let (sin, cos) = overlay.rotation.sin_cos();
+            Mat2::from_diagonal(overlay.size.recip())
+                * Mat2::from_cols(Vec2::new(cos, -sin), Vec2::new(sin, cos)).transpose()
+        });
+
+        Self {
+            rot_matrix: Mat3::from_quat(plane.rotation.inverse()),
+            offset: (plane.origin - camera).as_vec3(),
+            normal,
+            cell_phase,
+            coarse_cell_phase,
+            cell_period,
+            clip_polygon_start: clip_polygon.x,
+            clip_polygon_len: clip_polygon.y,
+            overlay_from_plane,
+            overlay_origin: overlay.map_or(Vec2::ZERO, |overlay| {
+                (overlay.origin.as_dvec2() - camera_plane).as_vec2()
+            }),
+            overlay: overlay.is_some() as u32,
+            cell_shift,
+            heatmap_origin,
+            heatmap_range,
+            heatmap_ramp_start: heatmap_ramp.x,
+            heatmap_ramp_len: heatmap_ramp.y,
+            cell_highlight_start: cell_highlights.x,
+            cell_highlight_len: cell_highlights.y,
+            cell_highlight_outline_width,
+            cursor_coord: cursor_coord.unwrap_or_default(),
+            cursor: cursor_coord.is_some() as u32,
+        }
+    }
+}
+
+/// Where the camera is within the periods of the grid, which is all the shader needs to draw the
+/// lines around it.
+#[derive(Debug, PartialEq)]
+struct CameraPhase {
+    cell_phase: Vec2,
+    coarse_cell_phase: Vec2,
+    cell_period: f32,
+    cell_shift: IVec2,
+}
+
+impl CameraPhase {
+    fn new(camera_cells: DVec2, periods: Option<(DVec2, DVec2)>, cartesian: bool) -> Self {
+        let Some((period, coarse_period)) = periods else {
+            return Self {
+                cell_phase: Vec2::ZERO,
+                coarse_cell_phase: Vec2::ZERO,
+                cell_period: f32::MAX,
+                cell_shift: IVec2::ZERO,
+            };
+        };
+        let cell_phase = camera_cells.rem_euclid(period);
+        Self {
+            cell_phase: cell_phase.as_vec2(),
+            coarse_cell_phase: camera_cells.rem_euclid(coarse_period).as_vec2(),
+            cell_period: period.max_element() as f32,
+            // Square cells repeat after a whole number of them, which the phase leaves out of the
+            // cells the shader sees. Both wrap around the same way past the range of i32.
+            cell_shift: if cartesian {
+                (camera_cells - cell_phase).as_i64vec2().as_ivec2()
+            } else {
+                IVec2::ZERO
+            },
+        }
+    }
+}
+
+/// Periods in cells after which the lines of the grid repeat along each axis. The second one is
+/// for the lines further apart than the first, and both are equal when all lines fit in the first.
+/// Polar grids are drawn around their origin and don't repeat.
+fn cell_periods(
+    settings: &InfiniteGridSettings,
+    view_distance_cells: f64,
+) -> Option<(DVec2, DVec2)> {
+    // Checkerboard fills alternate every cell, so square cells must repeat after an even count
+    let even = |period: f64| {
+        if period % 2. == 0. {
+            period
+        } else {
+            period * 2.
+        }
+    };
+    match settings.kind {
+        GridKind::Cartesian => match settings.subdivision {
+            GridSubdivision::Fixed => {
+                let period = even(
+                    settings.major_line_every.max(1) as f64
+                        * settings.super_major_line_every.unwrap_or(1).max(1) as f64,
+                );
+                Some((DVec2::splat(period), DVec2::splat(period)))
+            }
+            GridSubdivision::Adaptive { base } => {
+                // The visible powers of the base scale with the view distance. Keep the phase
+                // exact for the finest of them and let the coarsest use a larger period. Whole
+                // periods keep the shift of square cells whole when the camera is on the plane.
+                let base = base.max(2) as f64;
+                let exponent = (view_distance_cells.max(1e-6) * 1e3)
+                    .log(base)
+                    .ceil()
+                    .max(1.);
+                let coarse_exponent = exponent + (4. / base.log10()).ceil();
+                Some((
+                    DVec2::splat(even(base.powf(exponent))),
+                    DVec2::splat(even(base.powf(coarse_exponent))),
+                ))
+            }
+        },
+        GridKind::Polar { .. } => None,
+        GridKind::Hexagonal {
+            orientation: HexOrientation::PointyTop,
+        }
+        | GridKind::Triangular => {
+            let period = DVec2::new(1., 3f64.sqrt());
+            Some((period, period))
+        }
+        GridKind::Hexagonal {
+            orientation: HexOrientation::FlatTop,
+        } => {
+            let period = DVec2::new(3f64.sqrt(), 1.);
+            Some((period, period))
+        }
+    }
+}
+
+/// Spacing in cells of the finest lines the shader draws at `offset` from the camera, mostly
+/// faded in, which the cursor snaps to. Only adaptive subdivision skips whole cells.
+fn cursor_spacing(
+    settings: &InfiniteGridSettings,
+    plane: &GridPlane,
+    view: &ExtractedView,
+    offset: Vec3,
+) -> f64 {
+    let GridSubdivision::Adaptive { base } = settings.subdivision else {
+        return 1.;
+    };
+    let Some(derivative) = cells_per_pixel(settings, plane, view, offset) else {
+        return 1.;
+    };
+    // Same level of detail as the shader
+    let minor_width = match settings.line_width_mode {
+        LineWidthMode::Pixels => Vec2::splat(settings.minor_line_width),
+        LineWidthMode::World => settings.minor_line_width * settings.scale / derivative,
+    };
+    let base = base.max(2) as f32;
+    let min_cell_pixels = LOD_MIN_CELL_PIXELS.max(4. * minor_width.max_element());
+    let lod = (derivative.max_element() * min_cell_pixels).log(base);
+    if !lod.is_finite() {
+        return 1.;
+    }
+    // The finest level fades out as the level of detail goes up, so skip it once it's mostly gone
+    let exponent = lod.floor() + 1. + (lod.fract() > 0.5) as u32 as f32;
+    (base as f64).powf(exponent as f64).max(1.)
+}
+
+/// How many cells a pixel covers along each axis of the plane at `offset` from the camera, like
+/// `fwidth` of the cell coordinates in the shader. `None` when the point isn't in front of the
+/// camera.
+fn cells_per_pixel(
+    settings: &InfiniteGridSettings,
+    plane: &GridPlane,
+    view: &ExtractedView,
+    offset: Vec3,
+) -> Option<Vec2> {
+    let view_size = view.viewport.zw().as_vec2();
+    let world_from_view = Mat3::from(view.world_from_view.affine().matrix3);
+    let view_from_world = world_from_view.inverse();
+    let view_from_clip = view.clip_from_view.inverse();
+    let point = view_from_world * offset;
+    let normal = view_from_world * *plane.normal();
+    let clip = view.clip_from_view * point.extend(1.);
+    if clip.w <= 0. || view_size.min_element() == 0. {
+        return None;
+    }
+    let ndc = clip.xyz() / clip.w;
+    let unproject = |ndc: Vec3| {
+        let point = view_from_clip * ndc.extend(1.);
+        point.xyz() / point.w
+    };
+    let plane_from_world = Mat3::from_quat(plane.rotation.inverse());
+    let scale = settings.scale / settings.cell_size;
+    // Follows the rays through the neighbouring pixels to the plane
+    let mut derivative = Vec2::ZERO;
+    for pixel in [Vec2::new(2., 0.), Vec2::new(0., -2.)] {
+        let ndc_xy = ndc.xy() + pixel / view_size;
+        let near = unproject(ndc_xy.extend(ndc.z));
+        let far = unproject(ndc_xy.extend(ndc.z * 0.5));
+        let t = normal.dot(point - near) / normal.dot(far - near);
+        let step = near + (far - near) * t - point;
+        let cells = (plane_from_world * world_from_view * step).xz();
+        derivative += (cells * scale).abs();
+    }
+    derivative.is_finite().then_some(derivative)
 }
 
 #[derive(Debug, ShaderType)]
 pub struct GridDisplaySettingsUniform {
-    scale: f32,
-    // 1 / fadeout_distance
-    dist_fadeout_const: f32,
-    dot_fadeout_const: f32,
+    // scale / cell_size
+    scale: Vec2,
+    // Matches GridFade, along the distance to the camera and one minus the cosine of the angle to
+    // the grid's normal
+    fade_kind: u32,
+    grazing_fade_kind: u32,
+    // Start and end, or density in x
+    fade_params: Vec2,
+    grazing_fade_params: Vec2,
+    // 0 when the subdivision is fixed
+    lod_base: f32,
+    major_line_every: f32,
+    // 0 when there is no super-major tier
+    super_major_line_every: f32,
+    // 0 when line widths are in pixels, 1 when they are in world units
+    line_width_mode: u32,
+    minor_line_width: f32,
+    major_line_width: f32,
+    super_major_line_width: f32,
+    axis_line_width: f32,
+    minor_line_style: u32,
+    major_line_style: u32,
+    polar_ring_spacing: f32,
+    polar_spoke_count: f32,
+    // 1 when hexagons have a flat side along x instead of a vertex
+    hex_flat_top: u32,
+    // 1 / y_axis_fadeout_distance
+    y_axis_dist_fadeout_const: f32,
+    // Alpha below which masked grids are discarded
+    alpha_mask_cutoff: f32,
+    // 0 when unbounded, 1 for a rectangle and 2 for a circle
+    bounds_kind: u32,
+    // Half size of the rectangle, or radius of the circle in x
+    bounds_half_size: Vec2,
+    bounds_falloff: f32,
+    border_line_width: f32,
+    // Share of their color the lines lose in full shadow
+    shadow_darkening: f32,
+    // Whether to use the view's fog instead of the distance fadeout, 0 or 1
+    distance_fog: u32,
+    fill_fade_kind: u32,
+    fill_fade_params: Vec2,
+    // 0 when there is no crosshair or circle
+    cursor_crosshair_length: f32,
+    cursor_circle_radius: f32,
+    cursor_line_width: f32,
     x_axis_color: Vec3,
+    y_axis_color: Vec3,
     z_axis_color: Vec3,
     minor_line_color: Vec4,
     major_line_color: Vec4,
+    super_major_line_color: Vec4,
+    border_color: Vec4,
+    shadow_color: Vec4,
+    // Both are the same for solid fills
+    fill_color: Vec4,
+    fill_checker_color: Vec4,
+    cursor_color: Vec4,
 }
 
 impl GridDisplaySettingsUniform {
     fn from_settings(settings: &InfiniteGridSettings) -> Self {
+        let (polar_ring_spacing, polar_spoke_count) = match settings.kind {
+            GridKind::Polar {
+                ring_spacing,
+                spoke_count,
+            } => (ring_spacing.max(f32::EPSILON), spoke_count.max(1) as f32),
+            _ => (1., 1.),
+        };
+        // The deprecated fields still apply to the fades that replaced them, when those are left
+        // at their defaults
+        #[allow(deprecated)]
+        let fade = if settings.fade == InfiniteGridSettings::DEFAULT_FADE {
+            GridFade::Linear {
+                start: 0.,
+                end: settings.fadeout_distance,
+            }
+        } else {
+            settings.fade
+        };
+        #[allow(deprecated)]
+        let grazing_fade = if settings.grazing_fade == InfiniteGridSettings::DEFAULT_GRAZING_FADE {
+            GridFade::Linear {
+                start: 1. - settings.dot_fadeout_strength,
+                end: 1.,
+            }
+        } else {
+            settings.grazing_fade
+        };
+        let (fade_kind, fade_params) = fade_uniform(fade);
+        let (grazing_fade_kind, grazing_fade_params) = fade_uniform(grazing_fade);
+        let (fill_fade_kind, fill_fade_params) = fade_uniform(settings.fill_fade);
+        let (fill_color, fill_checker_color) = match settings.fill {
+            GridFill::None => (Color::NONE, Color::NONE),
+            GridFill::Solid(color) => (color, color),
+            GridFill::Checkerboard(color, checker_color) => (color, checker_color),
+        };
         Self {
-            scale: settings.scale,
-            dist_fadeout_const: 1. / settings.fadeout_distance,
-            dot_fadeout_const: 1. / settings.dot_fadeout_strength,
+            scale: settings.scale / settings.cell_size,
+            fade_kind,
+            grazing_fade_kind,
+            fade_params,
+            grazing_fade_params,
+            lod_base: match settings.subdivision {
+                GridSubdivision::Fixed => 0.,
+                GridSubdivision::Adaptive { base } => base.max(2) as f32,
+            },
+            major_line_every: settings.major_line_every.max(1) as f32,
+            super_major_line_every: settings
+                .super_major_line_every
+                .map_or(0., |every| every.max(1) as f32),
+            line_width_mode: match settings.line_width_mode {
+                LineWidthMode::Pixels => 0,
+                LineWidthMode::World => 1,
+            },
+            minor_line_width: settings.minor_line_width.max(0.),
+            major_line_width: settings.major_line_width.max(0.),
+            super_major_line_width: settings.super_major_line_width.max(0.),
+            axis_line_width: settings.axis_line_width.max(0.),
+            minor_line_style: line_style_index(settings.minor_line_style),
+            major_line_style: line_style_index(settings.major_line_style),
+            polar_ring_spacing,
+            polar_spoke_count,
+            hex_flat_top: matches!(
+                settings.kind,
+                GridKind::Hexagonal {
+                    orientation: HexOrientation::FlatTop
+                }
+            ) as u32,
+            y_axis_dist_fadeout_const: 1. / settings.y_axis_fadeout_distance.max(f32::EPSILON),
+            alpha_mask_cutoff: match settings.alpha_mode {
+                GridAlphaMode::Blend => 0.,
+                GridAlphaMode::Mask(cutoff) => cutoff.max(0.),
+            },
+            bounds_kind: match settings.bounds {
+                GridBounds::None => 0,
+                GridBounds::Rect { .. } => 1,
+                GridBounds::Circle { .. } => 2,
+            },
+            bounds_half_size: match settings.bounds {
+                GridBounds::None => Vec2::ZERO,
+                GridBounds::Rect { half_size } => half_size,
+                GridBounds::Circle { radius } => Vec2::splat(radius),
+            },
+            bounds_falloff: settings.bounds_falloff.max(0.),
+            border_line_width: settings.border_line_width.max(0.),
+            shadow_darkening: settings.shadow_darkening.clamp(0., 1.),
+            distance_fog: settings.distance_fog as u32,
+            fill_fade_kind,
+            fill_fade_params,
+            cursor_crosshair_length: settings.cursor.crosshair_length.unwrap_or(0.).max(0.),
+            cursor_circle_radius: settings.cursor.circle_radius.unwrap_or(0.).max(0.),
+            cursor_line_width: settings.cursor.line_width.max(0.),
             x_axis_color: settings.x_axis_color.to_linear().to_vec3(),
+            y_axis_color: settings
+                .y_axis_color
+                .unwrap_or(Color::NONE)
+                .to_linear()
+                .to_vec3(),
             z_axis_color: settings.z_axis_color.to_linear().to_vec3(),
             minor_line_color: settings.minor_line_color.to_linear().to_vec4(),
             major_line_color: settings.major_line_color.to_linear().to_vec4(),
+            super_major_line_color: settings.super_major_line_color.to_linear().to_vec4(),
+            border_color: settings
+                .border_color
+                .unwrap_or(Color::NONE)
+                .to_linear()
+                .to_vec4(),
+            shadow_color: settings
+                .shadow_color
+                .unwrap_or(Color::NONE)
+                .to_linear()
+                .to_vec4(),
+            fill_color: fill_color.to_linear().to_vec4(),
+            fill_checker_color: fill_checker_color.to_linear().to_vec4(),
+            cursor_color: settings.cursor.color.to_linear().to_vec4(),
         }
     }
 }
 
+fn line_style_index(style: GridLineStyle) -> u32 {
+    match style {
+        GridLineStyle::Solid => 0,
+        GridLineStyle::Dotted => 1,
+        GridLineStyle::Crosshair => 2,
+        GridLineStyle::Dashed => 3,
+    }
+}
+
+// Matches the shader
+const LOD_MIN_CELL_PIXELS: f32 = 8.;
+
+const FADE_NONE: u32 = 0;
+const FADE_LINEAR: u32 = 1;
+const FADE_EXPONENTIAL: u32 = 2;
+const FADE_EXPONENTIAL_SQUARED: u32 = 3;
+const FADE_SMOOTHSTEP: u32 = 4;
+
+fn fade_uniform(fade: GridFade) -> (u32, Vec2) {
+    match fade {
+        GridFade::None => (FADE_NONE, Vec2::ZERO),
+        GridFade::Linear { start, end } => (FADE_LINEAR, Vec2::new(start, end)),
+        GridFade::Exponential { density } => (FADE_EXPONENTIAL, Vec2::new(density, 0.)),
+        GridFade::ExponentialSquared { density } => {
+            (FADE_EXPONENTIAL_SQUARED, Vec2::new(density, 0.))
+        }
+        GridFade::Smoothstep { start, end } => (FADE_SMOOTHSTEP, Vec2::new(start, end)),
+    }
+}
+
 #[derive(Resource, Default)]
 struct InfiniteGridUniforms {
     uniforms: DynamicUniformBuffer<InfiniteGridUniform>,
@@ -118,31 +682,77 @@ struct GridDisplaySettingsUniforms {
     uniforms: DynamicUniformBuffer<GridDisplaySettingsUniform>,
 }
 
+// The vertices of the clip polygons of all grids, one after the other
+#[derive(Resource, Default)]
+struct GridClipPolygons {
+    points: StorageBuffer<Vec<Vec2>>,
+}
+
+// Same for the color ramps of the heatmaps
+#[derive(Resource, Default)]
+struct GridHeatmapRamps {
+    colors: StorageBuffer<Vec<Vec4>>,
+}
+
+// And for the highlighted cells
+#[derive(Resource, Default)]
+struct GridCellHighlightBuffer {
+    highlights: StorageBuffer<Vec<GpuCellHighlight>>,
+}
+
 #[derive(Component)]
 struct InfiniteGridUniformOffsets {
-    position_offset: u32,
     settings_offset: u32,
 }
 
+// The position uniforms depend on the view, so each view keeps track of those of every grid
+#[derive(Component)]
+struct ViewInfiniteGridUniformOffsets {
+    position_offsets: EntityHashMap<u32>,
+}
+
 #[derive(Component)]
 pub struct PerCameraSettingsUniformOffset {
     offset: u32,
 }
 
-#[derive(Resource)]
+// Kept from one frame to the next, and only recreated when one of the resources it binds changes
+#[derive(Component)]
 struct InfiniteGridBindGroup {
+    key: InfiniteGridBindGroupKey,
     value: BindGroup,
 }
 
+#[derive(PartialEq, Eq)]
+struct InfiniteGridBindGroupKey {
+    uniforms: [BufferId; 2],
+    overlay: (TextureViewId, SamplerId),
+    clip_polygons: Option<BufferId>,
+    heatmap: Option<(TextureViewId, BufferId)>,
+    cell_highlights: Option<BufferId>,
+}
+
 #[derive(Clone, ShaderType)]
 pub struct GridViewUniform {
     projection: Mat4,
     inverse_projection: Mat4,
     view: Mat4,
     inverse_view: Mat4,
-    world_position: Vec3,
+    // Last frame's clip_from_world, for positions relative to the camera's current position
+    previous_projection: Mat4,
+    // The camera's DistanceFog, laid out like Bevy's own fog uniform
+    fog_color: Vec4,
+    fog_be: Vec3,
+    fog_mode: u32,
+    fog_bi: Vec3,
 }
 
+const FOG_MODE_OFF: u32 = 0;
+const FOG_MODE_LINEAR: u32 = 1;
+const FOG_MODE_EXPONENTIAL: u32 = 2;
+const FOG_MODE_EXPONENTIAL_SQUARED: u32 = 3;
+const FOG_MODE_ATMOSPHERIC: u32 = 4;
+
 #[derive(Resource, Default)]
 pub struct GridViewUniforms {
     uniforms: DynamicUniformBuffer<GridViewUniform>,
@@ -181,27 +791,37 @@ impl<const I: usize, P: PhaseItem> RenderCommand<P> for SetGridViewBindGroup<I>
 struct SetInfiniteGridBindGroup<const I: usize>;
 
 impl<const I: usize, P: PhaseItem> RenderCommand<P> for SetInfiniteGridBindGroup<I> {
-    type Param = SRes<InfiniteGridBindGroup>;
-    type ViewQuery = Option<Read<PerCameraSettingsUniformOffset>>;
-    type ItemQuery = Read<InfiniteGridUniformOffsets>;
+    type Param = ();
+    type ViewQuery = (
+        Read<ViewInfiniteGridUniformOffsets>,
+        Option<Read<PerCameraSettingsUniformOffset>>,
+    );
+    type ItemQuery = (
+        Read<InfiniteGridUniformOffsets>,
+        Read<InfiniteGridBindGroup>,
+    );
 
     #[inline]
     fn render<'w>(
-        _item: &P,
-        camera_settings_offset: ROQueryItem<'w, '_, Self::ViewQuery>,
-        base_offsets: Option<ROQueryItem<'w, '_, Self::ItemQuery>>,
-        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        item: &P,
+        (view_offsets, camera_settings_offset): ROQueryItem<'w, '_, Self::ViewQuery>,
+        grid: Option<ROQueryItem<'w, '_, Self::ItemQuery>>,
+        _param: SystemParamItem<'w, '_, Self::Param>,
         pass: &mut bevy::render::render_phase::TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let Some(base_offsets) = base_offsets else {
-            warn!("PerCameraSettingsUniformOffset missing");
+        let Some((base_offsets, bind_group)) = grid else {
+            warn!("InfiniteGridBindGroup missing");
+            return RenderCommandResult::Skip;
+        };
+        let Some(&position_offset) = view_offsets.position_offsets.get(&item.entity()) else {
+            warn!("InfiniteGridUniform missing for this view");
             return RenderCommandResult::Skip;
         };
         pass.set_bind_group(
             I,
-            &bind_group.into_inner().value,
+            &bind_group.value,
             &[
-                base_offsets.position_offset,
+                position_offset,
                 camera_settings_offset
                     .map(|cs| cs.offset)
                     .unwrap_or(base_offsets.settings_offset),
@@ -236,20 +856,51 @@ fn prepare_grid_view_uniforms(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     mut view_uniforms: ResMut<GridViewUniforms>,
-    views: Query<(Entity, &ExtractedView)>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        Option<&PreviousViewData>,
+        Option<&DistanceFog>,
+    )>,
 ) {
     view_uniforms.uniforms.clear();
-    for (entity, camera) in views.iter() {
+    for (entity, camera, previous_view, fog) in views.iter() {
         let projection = camera.clip_from_view;
         let view = camera.world_from_view.to_matrix();
         let inverse_view = view.inverse();
+        let previous_projection =
+            previous_view.map_or(projection * inverse_view, |previous_view| {
+                previous_view.clip_from_world
+            }) * Mat4::from_translation(camera.world_from_view.translation());
+        let (fog_mode, fog_be, fog_bi) = match fog.map(|fog| &fog.falloff) {
+            None => (FOG_MODE_OFF, Vec3::ZERO, Vec3::ZERO),
+            Some(&FogFalloff::Linear { start, end }) => {
+                (FOG_MODE_LINEAR, Vec3::new(start, end, 0.), Vec3::ZERO)
+            }
+            Some(&FogFalloff::Exponential { density }) => {
+                (FOG_MODE_EXPONENTIAL, Vec3::new(density, 0., 0.), Vec3::ZERO)
+            }
+            Some(&FogFalloff::ExponentialSquared { density }) => (
+                FOG_MODE_EXPONENTIAL_SQUARED,
+                Vec3::new(density, 0., 0.),
+                Vec3::ZERO,
+            ),
+            Some(&FogFalloff::Atmospheric {
+                extinction,
+                inscattering,
+            }) => (FOG_MODE_ATMOSPHERIC, extinction, inscattering),
+        };
         commands.entity(entity).insert(GridViewUniformOffset {
             offset: view_uniforms.uniforms.push(&GridViewUniform {
                 projection,
                 view,
                 inverse_view,
                 inverse_projection: projection.inverse(),
-                world_position: camera.world_from_view.translation(),
+                previous_projection,
+                fog_color: fog.map_or(Vec4::ZERO, |fog| fog.color.to_linear().to_vec4()),
+                fog_be,
+                fog_mode,
+                fog_bi,
             }),
         });
     }
@@ -281,6 +932,7 @@ fn prepare_grid_view_bind_groups(
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn extract_infinite_grids(
     mut commands: Commands,
     grids: Extract<
@@ -289,27 +941,74 @@ fn extract_infinite_grids(
             &InfiniteGridSettings,
             &GlobalTransform,
             &RenderVisibleEntities,
+            Option<&GridFloatingOrigin>,
+            Option<&GridClipPolygon>,
+            Option<&GridImageOverlay>,
+            Option<(&GridHeatmap, Option<&PackedHeatmapCells>)>,
+            Option<&GridCellHighlights>,
+            Option<&GridCursor>,
         )>,
     >,
 ) {
     let extracted: Vec<_> = grids
         .iter()
-        .map(|(entity, grid, transform, visible_entities)| {
-            (
+        .map(
+            |(
                 entity,
+                grid,
+                transform,
+                visible_entities,
+                floating_origin,
+                clip_polygon,
+                overlay,
+                heatmap,
+                highlights,
+                cursor,
+            )| {
                 (
-                    ExtractedInfiniteGrid {
-                        transform: *transform,
-                        grid: *grid,
-                    },
-                    visible_entities.clone(),
-                ),
-            )
-        })
+                    entity,
+                    (
+                        ExtractedInfiniteGrid {
+                            transform: *transform,
+                            grid: *grid,
+                            floating_origin: floating_origin.copied(),
+                            clip_polygon: clip_polygon.cloned(),
+                            overlay: overlay.cloned(),
+                            heatmap: heatmap.and_then(extract_heatmap),
+                            highlights: highlights.cloned(),
+                            cursor: cursor.and_then(|cursor| cursor.position),
+                        },
+                        visible_entities.clone(),
+                    ),
+                )
+            },
+        )
         .collect();
     commands.try_insert_batch(extracted);
 }
 
+fn extract_heatmap(
+    (heatmap, packed): (&GridHeatmap, Option<&PackedHeatmapCells>),
+) -> Option<ExtractedHeatmap> {
+    let (image, origin) = match &heatmap.values {
+        HeatmapValues::Image { image, origin } => (image.clone(), *origin),
+        HeatmapValues::Cells(_) => packed.map(|packed| (packed.image.clone(), packed.origin))?,
+    };
+    if heatmap.ramp.is_empty() {
+        return None;
+    }
+    Some(ExtractedHeatmap {
+        image,
+        origin,
+        ramp: heatmap
+            .ramp
+            .iter()
+            .map(|color| color.to_linear().to_vec4())
+            .collect(),
+        range: heatmap.range,
+    })
+}
+
 fn extract_per_camera_settings(
     mut commands: Commands,
     cameras: Extract<Query<(RenderEntity, &InfiniteGridSettings), With<Camera>>>,
@@ -321,29 +1020,86 @@ fn extract_per_camera_settings(
     commands.try_insert_batch(extracted);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn prepare_infinite_grids(
     mut commands: Commands,
     grids: Query<(Entity, &ExtractedInfiniteGrid)>,
     cameras: Query<(Entity, &InfiniteGridSettings), With<ExtractedView>>,
+    views: Query<(Entity, &ExtractedView, Option<&InfiniteGridSettings>), With<ExtractedCamera>>,
     mut position_uniforms: ResMut<InfiniteGridUniforms>,
     mut settings_uniforms: ResMut<GridDisplaySettingsUniforms>,
+    mut clip_polygons: ResMut<GridClipPolygons>,
+    mut heatmap_ramps: ResMut<GridHeatmapRamps>,
+    mut cell_highlights: ResMut<GridCellHighlightBuffer>,
+    images: Res<RenderAssets<GpuImage>>,
+    pipeline: Res<InfiniteGridPipeline>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
 ) {
     position_uniforms.uniforms.clear();
     settings_uniforms.uniforms.clear();
+
+    let clip_points = clip_polygons.points.get_mut();
+    clip_points.clear();
+    let clip_polygon_ranges: EntityHashMap<UVec2> = grids
+        .iter()
+        .filter_map(|(entity, extracted)| {
+            let points = &extracted.clip_polygon.as_ref()?.points;
+            if !extracted.features(pipeline.storage_buffers).clip_polygon {
+                if points.len() >= 3 {
+                    warn_once!("Grid clip polygons need storage buffers, which this device lacks");
+                }
+                return None;
+            }
+            let range = UVec2::new(clip_points.len() as u32, points.len() as u32);
+            clip_points.extend_from_slice(points);
+            Some((entity, range))
+        })
+        .collect();
+
+    let ramp_colors = heatmap_ramps.colors.get_mut();
+    ramp_colors.clear();
+    let heatmap_ramp_ranges: EntityHashMap<UVec2> = grids
+        .iter()
+        .filter_map(|(entity, extracted)| {
+            let heatmap = extracted.heatmap.as_ref()?;
+            if !extracted.features(pipeline.storage_buffers).heatmap {
+                warn_once!("Grid heatmaps need storage buffers, which this device lacks");
+                return None;
+            }
+            let range = UVec2::new(ramp_colors.len() as u32, heatmap.ramp.len() as u32);
+            ramp_colors.extend_from_slice(&heatmap.ramp);
+            Some((entity, range))
+        })
+        .collect();
+
+    // The shader binary searches the highlights of its grid for the cell of each fragment
+    let gpu_highlights = cell_highlights.highlights.get_mut();
+    gpu_highlights.clear();
+    let cell_highlight_ranges: EntityHashMap<UVec2> = grids
+        .iter()
+        .filter_map(|(entity, extracted)| {
+            let highlights = extracted.highlights.as_ref()?;
+            if !extracted.features(pipeline.storage_buffers).cell_highlights {
+                if !highlights.cells.is_empty() {
+                    warn_once!(
+                        "Grid cell highlights need storage buffers, which this device lacks"
+                    );
+                }
+                return None;
+            }
+            let cells = sorted_cell_highlights(&highlights.cells);
+            let range = UVec2::new(gpu_highlights.len() as u32, cells.len() as u32);
+            gpu_highlights.extend(cells.into_iter().map(|highlight| GpuCellHighlight {
+                cell: highlight.cell,
+                fill_color: highlight.fill_color.to_linear().to_vec4(),
+                outline_color: highlight.outline_color.to_linear().to_vec4(),
+            }));
+            Some((entity, range))
+        })
+        .collect();
     for (entity, extracted) in &grids {
-        let transform = extracted.transform;
-        let t = transform.compute_transform();
-        let offset = transform.translation();
-        let normal = transform.up();
-        let rot_matrix = Mat3::from_quat(t.rotation.inverse());
         commands.entity(entity).insert(InfiniteGridUniformOffsets {
-            position_offset: position_uniforms.uniforms.push(&InfiniteGridUniform {
-                rot_matrix,
-                offset,
-                normal: *normal,
-            }),
             settings_offset: settings_uniforms
                 .uniforms
                 .push(&GridDisplaySettingsUniform::from_settings(&extracted.grid)),
@@ -360,6 +1116,47 @@ fn prepare_infinite_grids(
             });
     }
 
+    for (entity, view, camera_settings) in &views {
+        let position_offsets = grids
+            .iter()
+            .map(|(grid_entity, extracted)| {
+                let settings = camera_settings.unwrap_or(&extracted.grid);
+                let clip_polygon = clip_polygon_ranges
+                    .get(&grid_entity)
+                    .copied()
+                    .unwrap_or_default();
+                // Overlays are left out until their image is loaded
+                let overlay = extracted
+                    .overlay
+                    .as_ref()
+                    .filter(|overlay| images.get(&overlay.image).is_some());
+                // Heatmaps are left out until their image is loaded, like overlays
+                let heatmap = extracted
+                    .heatmap
+                    .as_ref()
+                    .filter(|heatmap| images.get(&heatmap.image).is_some())
+                    .zip(heatmap_ramp_ranges.get(&grid_entity).copied());
+                let highlights = extracted
+                    .highlights
+                    .as_ref()
+                    .zip(cell_highlight_ranges.get(&grid_entity).copied());
+                let uniform = InfiniteGridUniform::new(
+                    extracted,
+                    settings,
+                    view,
+                    clip_polygon,
+                    overlay,
+                    heatmap,
+                    highlights,
+                );
+                (grid_entity, position_uniforms.uniforms.push(&uniform))
+            })
+            .collect();
+        commands
+            .entity(entity)
+            .insert(ViewInfiniteGridUniformOffsets { position_offsets });
+    }
+
     position_uniforms
         .uniforms
         .write_buffer(&render_device, &render_queue);
@@ -367,84 +1164,412 @@ fn prepare_infinite_grids(
     settings_uniforms
         .uniforms
         .write_buffer(&render_device, &render_queue);
+
+    // Empty storage buffers can't be bound, and nothing binds this one when no grid clips
+    if !clip_polygons.points.get().is_empty() {
+        clip_polygons
+            .points
+            .write_buffer(&render_device, &render_queue);
+    }
+
+    if !heatmap_ramps.colors.get().is_empty() {
+        heatmap_ramps
+            .colors
+            .write_buffer(&render_device, &render_queue);
+    }
+
+    if !cell_highlights.highlights.get().is_empty() {
+        cell_highlights
+            .highlights
+            .write_buffer(&render_device, &render_queue);
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn prepare_bind_groups_for_infinite_grids(
     mut commands: Commands,
+    grids: Query<(
+        Entity,
+        &ExtractedInfiniteGrid,
+        Option<&InfiniteGridBindGroup>,
+    )>,
     position_uniforms: Res<InfiniteGridUniforms>,
     settings_uniforms: Res<GridDisplaySettingsUniforms>,
+    clip_polygons: Res<GridClipPolygons>,
+    heatmap_ramps: Res<GridHeatmapRamps>,
+    cell_highlights: Res<GridCellHighlightBuffer>,
+    images: Res<RenderAssets<GpuImage>>,
+    fallback_image: Res<FallbackImage>,
     pipeline: Res<InfiniteGridPipeline>,
     pipeline_cache: Res<PipelineCache>,
     render_device: Res<RenderDevice>,
 ) {
-    let Some((position_binding, settings_binding)) = position_uniforms
-        .uniforms
-        .binding()
-        .zip(settings_uniforms.uniforms.binding())
-    else {
+    let (Some(position_buffer), Some(settings_buffer)) = (
+        position_uniforms.uniforms.buffer(),
+        settings_uniforms.uniforms.buffer(),
+    ) else {
+        return;
+    };
+    // Only there when a grid uses them
+    let clip_polygon_buffer = clip_polygons.points.buffer();
+    let heatmap_ramp_buffer = heatmap_ramps.colors.buffer();
+    let cell_highlight_buffer = cell_highlights.highlights.buffer();
+    let (Some(position_binding), Some(settings_binding)) = (
+        position_uniforms.uniforms.binding(),
+        settings_uniforms.uniforms.binding(),
+    ) else {
         return;
     };
 
-    let bind_group = render_device.create_bind_group(
-        "infinite-grid-bind-group",
-        &pipeline_cache.get_bind_group_layout(&pipeline.infinite_grid_layout),
-        &BindGroupEntries::sequential((position_binding.clone(), settings_binding.clone())),
-    );
-    commands.insert_resource(InfiniteGridBindGroup { value: bind_group });
+    for (entity, extracted, bind_group) in &grids {
+        let features = extracted.features(pipeline.storage_buffers);
+        // Grids without an overlay or a heatmap, or whose images aren't loaded yet, bind the
+        // fallback image instead and never sample it
+        let image = extracted
+            .overlay
+            .as_ref()
+            .and_then(|overlay| images.get(&overlay.image))
+            .unwrap_or(&fallback_image.d2);
+        let heatmap_image = extracted
+            .heatmap
+            .as_ref()
+            .and_then(|heatmap| images.get(&heatmap.image))
+            .unwrap_or(&fallback_image.d2);
+        let clip_polygon_buffer = clip_polygon_buffer.filter(|_| features.clip_polygon);
+        let heatmap_ramp_buffer = heatmap_ramp_buffer.filter(|_| features.heatmap);
+        let cell_highlight_buffer = cell_highlight_buffer.filter(|_| features.cell_highlights);
+        let key = InfiniteGridBindGroupKey {
+            uniforms: [position_buffer.id(), settings_buffer.id()],
+            overlay: (image.texture_view.id(), image.sampler.id()),
+            clip_polygons: clip_polygon_buffer.map(|buffer| buffer.id()),
+            heatmap: heatmap_ramp_buffer
+                .map(|buffer| (heatmap_image.texture_view.id(), buffer.id())),
+            cell_highlights: cell_highlight_buffer.map(|buffer| buffer.id()),
+        };
+        if bind_group.is_some_and(|bind_group| bind_group.key == key) {
+            continue;
+        }
+        let mut entries = BindGroupEntries::sequential((
+            position_binding.clone(),
+            settings_binding.clone(),
+            &image.texture_view,
+            &image.sampler,
+        ))
+        .to_vec();
+        if let Some(clip_polygon_buffer) = clip_polygon_buffer {
+            entries.push(BindGroupEntry {
+                binding: 5,
+                resource: clip_polygon_buffer.as_entire_binding(),
+            });
+        }
+        if let Some(heatmap_ramp_buffer) = heatmap_ramp_buffer {
+            entries.extend([
+                BindGroupEntry {
+                    binding: 4,
+                    resource: (&heatmap_image.texture_view).into_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: heatmap_ramp_buffer.as_entire_binding(),
+                },
+            ]);
+        }
+        if let Some(cell_highlight_buffer) = cell_highlight_buffer {
+            entries.push(BindGroupEntry {
+                binding: 7,
+                resource: cell_highlight_buffer.as_entire_binding(),
+            });
+        }
+        let value = render_device.create_bind_group(
+            "infinite-grid-bind-group",
+            &pipeline_cache.get_bind_group_layout(&pipeline.infinite_grid_layout(features)),
+            &entries,
+        );
+        commands
+            .entity(entity)
+            .insert(InfiniteGridBindGroup { key, value });
+    }
 }
 
-#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn queue_infinite_grids(
     pipeline_cache: Res<PipelineCache>,
     transparent_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    alpha_mask_draw_functions: Res<DrawFunctions<AlphaMask3d>>,
+    prepass_draw_functions: Res<DrawFunctions<AlphaMask3dPrepass>>,
     pipeline: Res<InfiniteGridPipeline>,
     mut pipelines: ResMut<SpecializedRenderPipelines<InfiniteGridPipeline>>,
     infinite_grids: Query<&ExtractedInfiniteGrid>,
     mut transparent_render_phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
-    mut views: Query<(&ExtractedView, &RenderVisibleEntities, &Msaa)>,
+    mut alpha_mask_render_phases: ResMut<ViewBinnedRenderPhases<AlphaMask3d>>,
+    mut prepass_render_phases: ResMut<ViewBinnedRenderPhases<AlphaMask3dPrepass>>,
+    mut views: Query<(
+        &ExtractedView,
+        &RenderVisibleEntities,
+        &Msaa,
+        Option<&InfiniteGridSettings>,
+        (
+            Has<NormalPrepass>,
+            Has<MotionVectorPrepass>,
+            Has<DeferredPrepass>,
+        ),
+        (
+            Option<&ViewPrepassTextures>,
+            Has<OrderIndependentTransparencySettings>,
+            Has<ExtractedAtmosphere>,
+            Option<&ShadowFilteringMethod>,
+        ),
+    )>,
+    change_tick: SystemChangeTick,
 ) {
     let draw_function_id = transparent_draw_functions
         .read()
         .get_id::<DrawInfiniteGrid>()
         .unwrap();
+    let shadowed_draw_function_id = transparent_draw_functions
+        .read()
+        .get_id::<DrawShadowedInfiniteGrid>()
+        .unwrap();
+    let alpha_mask_draw_function_id = alpha_mask_draw_functions
+        .read()
+        .get_id::<DrawInfiniteGrid>()
+        .unwrap();
+    let shadowed_alpha_mask_draw_function_id = alpha_mask_draw_functions
+        .read()
+        .get_id::<DrawShadowedInfiniteGrid>()
+        .unwrap();
+    let prepass_draw_function_id = prepass_draw_functions
+        .read()
+        .get_id::<DrawInfiniteGrid>()
+        .unwrap();
 
-    for (view, entities, msaa) in views.iter_mut() {
+    for (
+        view,
+        entities,
+        msaa,
+        camera_settings,
+        (normal_prepass, motion_vector_prepass, deferred),
+        (prepass_textures, oit, atmosphere, shadow_filtering_method),
+    ) in views.iter_mut()
+    {
         let Some(phase) = transparent_render_phases.get_mut(&view.retained_view_entity) else {
             continue;
         };
+        // The binned phases keep their items from one frame to the next, and drop those that
+        // weren't queued again
+        let mut alpha_mask_phase = alpha_mask_render_phases.get_mut(&view.retained_view_entity);
+        // Deferred prepasses have more targets than the grid knows how to fill
+        let mut prepass_phase = prepass_render_phases
+            .get_mut(&view.retained_view_entity)
+            .filter(|_| !deferred);
 
-        let mesh_key = MeshPipelineKey::from_hdr(view.hdr);
-        let pipeline_id = pipelines.specialize(
-            &pipeline_cache,
-            &pipeline,
-            GridPipelineKey {
-                mesh_key,
-                sample_count: msaa.samples(),
-            },
-        );
+        let is_orthographic = view.clip_from_view.w_axis.w == 1.0;
+        let mut mesh_key = MeshPipelineKey::from_hdr(view.hdr);
+        if is_orthographic {
+            mesh_key |= MeshPipelineKey::VIEW_PROJECTION_ORTHOGRAPHIC;
+        }
+        let mut prepass_mesh_key = mesh_key;
+        if normal_prepass {
+            prepass_mesh_key |= MeshPipelineKey::NORMAL_PREPASS;
+        }
+        if motion_vector_prepass {
+            prepass_mesh_key |= MeshPipelineKey::MOTION_VECTOR_PREPASS;
+        }
+        // Grids that receive shadows bind the same view resources as the meshes of this view
+        let mut view_layout_key = MeshPipelineViewLayoutKey::from(*msaa)
+            | MeshPipelineViewLayoutKey::from(prepass_textures);
+        if oit {
+            view_layout_key |= MeshPipelineViewLayoutKey::OIT_ENABLED;
+        }
+        if atmosphere {
+            view_layout_key |= MeshPipelineViewLayoutKey::ATMOSPHERE;
+        }
+        let shadowed_mesh_key = mesh_key
+            | match shadow_filtering_method.copied().unwrap_or_default() {
+                ShadowFilteringMethod::Hardware2x2 => {
+                    MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2
+                }
+                ShadowFilteringMethod::Gaussian => MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN,
+                ShadowFilteringMethod::Temporal => MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL,
+            };
         for &entity in entities.iter::<InfiniteGridSettings>() {
-            if !infinite_grids
-                .get(entity.0)
-                .map(|grid| plane_check(&grid.transform, view.world_from_view.translation()))
-                .unwrap_or(false)
-            {
+            let Ok(grid) = infinite_grids.get(entity.0) else {
                 continue;
+            };
+            let plane_visible = if is_orthographic {
+                plane_check_orthographic(&grid.transform, *view.world_from_view.forward())
+            } else {
+                let camera = view.world_from_view.translation().as_dvec3() + grid.origin_shift();
+                plane_check(&grid.transform, camera)
+            };
+            // Settings on the camera replace the grid's own, including its kind
+            let settings = camera_settings.unwrap_or(&grid.grid);
+            let kind = GridKindKey::from_kind(settings.kind);
+            // Transparent items are sorted by their view space z, which is negative in front
+            let distance = -(sort_depth(grid, view) + settings.sort_bias);
+            let shadows = settings.receive_shadows.then_some(view_layout_key);
+            let features = grid.features(pipeline.storage_buffers);
+            let (plane_mesh_key, plane_draw_function_id, plane_alpha_mask_draw_function_id) =
+                if settings.receive_shadows {
+                    (
+                        shadowed_mesh_key,
+                        shadowed_draw_function_id,
+                        shadowed_alpha_mask_draw_function_id,
+                    )
+                } else {
+                    (mesh_key, draw_function_id, alpha_mask_draw_function_id)
+                };
+            if plane_visible {
+                match settings.alpha_mode {
+                    GridAlphaMode::Blend => {
+                        let pipeline_id = pipelines.specialize(
+                            &pipeline_cache,
+                            &pipeline,
+                            GridPipelineKey {
+                                mesh_key: plane_mesh_key,
+                                sample_count: msaa.samples(),
+                                kind,
+                                y_axis: false,
+                                phase: GridPhaseKey::Transparent,
+                                shadows,
+                                features,
+                            },
+                        );
+                        phase.items.push(Transparent3d {
+                            pipeline: pipeline_id,
+                            entity,
+                            draw_function: plane_draw_function_id,
+                            distance,
+                            batch_range: 0..1,
+                            extra_index: PhaseItemExtraIndex::None,
+                            indexed: false,
+                        });
+                    }
+                    GridAlphaMode::Mask(_) => {
+                        if let Some(alpha_mask_phase) = alpha_mask_phase.as_mut() {
+                            let pipeline_id = pipelines.specialize(
+                                &pipeline_cache,
+                                &pipeline,
+                                GridPipelineKey {
+                                    mesh_key: plane_mesh_key,
+                                    sample_count: msaa.samples(),
+                                    kind,
+                                    y_axis: false,
+                                    phase: GridPhaseKey::AlphaMask,
+                                    shadows,
+                                    features,
+                                },
+                            );
+                            alpha_mask_phase.add(
+                                grid_batch_set_key(pipeline_id, plane_alpha_mask_draw_function_id),
+                                grid_bin_key(),
+                                entity,
+                                InputUniformIndex::default(),
+                                BinnedRenderPhaseType::NonMesh,
+                                change_tick.this_run(),
+                            );
+                        }
+                        if let Some(prepass_phase) = prepass_phase.as_mut() {
+                            let pipeline_id = pipelines.specialize(
+                                &pipeline_cache,
+                                &pipeline,
+                                GridPipelineKey {
+                                    mesh_key: prepass_mesh_key,
+                                    sample_count: msaa.samples(),
+                                    kind,
+                                    y_axis: false,
+                                    phase: GridPhaseKey::Prepass,
+                                    shadows: None,
+                                    features,
+                                },
+                            );
+                            prepass_phase.add(
+                                grid_batch_set_key(pipeline_id, prepass_draw_function_id),
+                                grid_bin_key(),
+                                entity,
+                                InputUniformIndex::default(),
+                                BinnedRenderPhaseType::NonMesh,
+                                change_tick.this_run(),
+                            );
+                        }
+                    }
+                }
+            }
+            // The vertical axis is drawn by a second pass over the same grid, after the plane
+            if settings.y_axis_color.is_some() {
+                let pipeline_id = pipelines.specialize(
+                    &pipeline_cache,
+                    &pipeline,
+                    GridPipelineKey {
+                        mesh_key,
+                        sample_count: msaa.samples(),
+                        kind: GridKindKey::Cartesian,
+                        y_axis: true,
+                        phase: GridPhaseKey::Transparent,
+                        shadows: None,
+                        features,
+                    },
+                );
+                phase.items.push(Transparent3d {
+                    pipeline: pipeline_id,
+                    entity,
+                    draw_function: draw_function_id,
+                    distance,
+                    batch_range: 0..1,
+                    extra_index: PhaseItemExtraIndex::None,
+                    indexed: false,
+                });
             }
-            phase.items.push(Transparent3d {
-                pipeline: pipeline_id,
-                entity,
-                draw_function: draw_function_id,
-                distance: f32::NEG_INFINITY,
-                batch_range: 0..1,
-                extra_index: PhaseItemExtraIndex::None,
-                indexed: false,
-            });
         }
     }
 }
 
-fn plane_check(plane: &GlobalTransform, point: Vec3) -> bool {
-    plane.up().dot(plane.translation() - point).abs() > f32::EPSILON
+// Grids aren't meshes, so their keys only tell their pipelines and draw functions apart
+fn grid_batch_set_key(
+    pipeline: CachedRenderPipelineId,
+    draw_function: DrawFunctionId,
+) -> OpaqueNoLightmap3dBatchSetKey {
+    OpaqueNoLightmap3dBatchSetKey {
+        pipeline,
+        draw_function,
+        material_bind_group_index: None,
+        vertex_slab: SlabId::default(),
+        index_slab: None,
+    }
+}
+
+fn grid_bin_key() -> OpaqueNoLightmap3dBinKey {
+    OpaqueNoLightmap3dBinKey {
+        asset_id: AssetId::<Mesh>::invalid().untyped(),
+    }
+}
+
+// Depth of the point the camera looks at on the plane, or its distance to the plane when it looks
+// away from it. Sorting by it keeps grids behind what stands on them and stacked grids in order.
+fn sort_depth(grid: &ExtractedInfiniteGrid, view: &ExtractedView) -> f32 {
+    let normal = grid.transform.up().as_dvec3();
+    let camera = view.world_from_view.translation().as_dvec3() + grid.origin_shift();
+    let height = normal.dot(camera - grid.transform.translation().as_dvec3());
+    let facing = -normal.dot(view.world_from_view.forward().as_dvec3());
+    if height * facing > 0. {
+        (height / facing) as f32
+    } else {
+        height.abs() as f32
+    }
+}
+
+fn plane_check(plane: &GlobalTransform, point: DVec3) -> bool {
+    plane
+        .up()
+        .as_dvec3()
+        .dot(plane.translation().as_dvec3() - point)
+        .abs()
+        > f32::EPSILON as f64
+}
+
+// Orthographic views see the plane from anywhere, unless they look along it
+fn plane_check_orthographic(plane: &GlobalTransform, view_direction: Vec3) -> bool {
+    plane.up().dot(view_direction).abs() > f32::EPSILON
 }
 
 type DrawInfiniteGrid = (
@@ -454,14 +1579,60 @@ type DrawInfiniteGrid = (
     FinishDrawInfiniteGrid,
 );
 
+// Grids that receive shadows read the lights from Bevy's mesh view bind groups, so theirs come after
+type DrawShadowedInfiniteGrid = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshViewBindingArrayBindGroup<1>,
+    SetGridViewBindGroup<2>,
+    SetInfiniteGridBindGroup<3>,
+    FinishDrawInfiniteGrid,
+);
+
 #[derive(Resource)]
 struct InfiniteGridPipeline {
+    mesh_pipeline: MeshPipeline,
     view_layout: BindGroupLayoutDescriptor,
-    infinite_grid_layout: BindGroupLayoutDescriptor,
+    // Whether the fragment shader can read the storage buffers of the optional features
+    storage_buffers: bool,
+}
+
+impl InfiniteGridPipeline {
+    fn infinite_grid_layout(&self, features: GridFeaturesKey) -> BindGroupLayoutDescriptor {
+        let mut entries = BindGroupLayoutEntries::sequential(
+            ShaderStages::FRAGMENT,
+            (
+                uniform_buffer::<InfiniteGridUniform>(true),
+                uniform_buffer::<GridDisplaySettingsUniform>(true),
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                sampler(SamplerBindingType::Filtering),
+            ),
+        )
+        .to_vec();
+        if features.clip_polygon {
+            entries.push(
+                storage_buffer_read_only::<Vec<Vec2>>(false).build(5, ShaderStages::FRAGMENT),
+            );
+        }
+        if features.heatmap {
+            entries.extend([
+                texture_2d(TextureSampleType::Float { filterable: false })
+                    .build(4, ShaderStages::FRAGMENT),
+                storage_buffer_read_only::<Vec<Vec4>>(false).build(6, ShaderStages::FRAGMENT),
+            ]);
+        }
+        if features.cell_highlights {
+            entries.push(
+                storage_buffer_read_only::<Vec<GpuCellHighlight>>(false)
+                    .build(7, ShaderStages::FRAGMENT),
+            );
+        }
+        BindGroupLayoutDescriptor::new("infinite-grid-bind-group-layout", &entries)
+    }
 }
 
 impl FromWorld for InfiniteGridPipeline {
-    fn from_world(_world: &mut World) -> Self {
+    fn from_world(world: &mut World) -> Self {
         let view_layout = BindGroupLayoutDescriptor::new(
             "grid-view-bind-group-layout",
             &BindGroupLayoutEntries::single(
@@ -469,20 +1640,15 @@ impl FromWorld for InfiniteGridPipeline {
                 uniform_buffer::<GridViewUniform>(true),
             ),
         );
-        let infinite_grid_layout = BindGroupLayoutDescriptor::new(
-            "infinite-grid-bind-group-layout",
-            &BindGroupLayoutEntries::sequential(
-                ShaderStages::FRAGMENT,
-                (
-                    uniform_buffer::<InfiniteGridUniform>(true),
-                    uniform_buffer::<GridDisplaySettingsUniform>(true),
-                ),
-            ),
-        );
 
         Self {
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
             view_layout,
-            infinite_grid_layout,
+            storage_buffers: world
+                .resource::<RenderDevice>()
+                .limits()
+                .max_storage_buffers_per_shader_stage
+                >= 3,
         }
     }
 }
@@ -491,6 +1657,45 @@ impl FromWorld for InfiniteGridPipeline {
 pub struct GridPipelineKey {
     mesh_key: MeshPipelineKey,
     sample_count: u32,
+    kind: GridKindKey,
+    y_axis: bool,
+    phase: GridPhaseKey,
+    // Layout of the view's mesh view bind groups when the grid receives shadows
+    shadows: Option<MeshPipelineViewLayoutKey>,
+    features: GridFeaturesKey,
+}
+
+#[derive(Hash, PartialEq, Eq, Clone, Copy)]
+struct GridFeaturesKey {
+    clip_polygon: bool,
+    heatmap: bool,
+    cell_highlights: bool,
+}
+
+#[derive(Hash, PartialEq, Eq, Clone, Copy)]
+enum GridPhaseKey {
+    Transparent,
+    AlphaMask,
+    Prepass,
+}
+
+#[derive(Hash, PartialEq, Eq, Clone, Copy)]
+enum GridKindKey {
+    Cartesian,
+    Polar,
+    Hexagonal,
+    Triangular,
+}
+
+impl GridKindKey {
+    fn from_kind(kind: GridKind) -> Self {
+        match kind {
+            GridKind::Cartesian => Self::Cartesian,
+            GridKind::Polar { .. } => Self::Polar,
+            GridKind::Hexagonal { .. } => Self::Hexagonal,
+            GridKind::Triangular => Self::Triangular,
+        }
+    }
 }
 
 impl SpecializedRenderPipeline for InfiniteGridPipeline {
@@ -503,13 +1708,104 @@ impl SpecializedRenderPipeline for InfiniteGridPipeline {
             TextureFormat::bevy_default()
         };
 
+        let mut shader_defs = Vec::new();
+        if key
+            .mesh_key
+            .contains(MeshPipelineKey::VIEW_PROJECTION_ORTHOGRAPHIC)
+        {
+            shader_defs.push("VIEW_PROJECTION_ORTHOGRAPHIC".into());
+        }
+        match key.kind {
+            GridKindKey::Cartesian => {}
+            GridKindKey::Polar => shader_defs.push("GRID_POLAR".into()),
+            GridKindKey::Hexagonal => shader_defs.push("GRID_HEXAGONAL".into()),
+            GridKindKey::Triangular => shader_defs.push("GRID_TRIANGULAR".into()),
+        }
+
+        let normal_prepass = key.mesh_key.contains(MeshPipelineKey::NORMAL_PREPASS);
+        let motion_vector_prepass = key
+            .mesh_key
+            .contains(MeshPipelineKey::MOTION_VECTOR_PREPASS);
+        let targets = match key.phase {
+            GridPhaseKey::Transparent => vec![Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+            GridPhaseKey::AlphaMask => {
+                shader_defs.push("ALPHA_MASK".into());
+                vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })]
+            }
+            GridPhaseKey::Prepass => {
+                shader_defs.push("ALPHA_MASK".into());
+                shader_defs.push("PREPASS".into());
+                if normal_prepass {
+                    shader_defs.push("NORMAL_PREPASS".into());
+                }
+                if motion_vector_prepass {
+                    shader_defs.push("MOTION_VECTOR_PREPASS".into());
+                }
+                prepass_target_descriptors(normal_prepass, motion_vector_prepass, false)
+            }
+        };
+        let depth_write_enabled = key.phase != GridPhaseKey::Transparent;
+
+        if key.features.clip_polygon {
+            shader_defs.push("GRID_CLIP_POLYGON".into());
+        }
+        if key.features.heatmap {
+            shader_defs.push("GRID_HEATMAP".into());
+        }
+        if key.features.cell_highlights {
+            shader_defs.push("GRID_CELL_HIGHLIGHTS".into());
+        }
+
+        let mut layout = vec![
+            self.view_layout.clone(),
+            self.infinite_grid_layout(key.features),
+        ];
+        if let Some(layout_key) = key.shadows {
+            let mesh_view_layout = self.mesh_pipeline.get_view_layout(layout_key);
+            layout.splice(
+                0..0,
+                [
+                    mesh_view_layout.main_layout.clone(),
+                    mesh_view_layout.binding_array_layout.clone(),
+                ],
+            );
+            shader_defs.push("GRID_SHADOWS".into());
+            let shadow_filter_method = key
+                .mesh_key
+                .intersection(MeshPipelineKey::SHADOW_FILTER_METHOD_RESERVED_BITS);
+            if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2 {
+                shader_defs.push("SHADOW_FILTER_METHOD_HARDWARE_2X2".into());
+            } else if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN {
+                shader_defs.push("SHADOW_FILTER_METHOD_GAUSSIAN".into());
+            } else if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL {
+                shader_defs.push("SHADOW_FILTER_METHOD_TEMPORAL".into());
+            }
+        }
+        let first_grid_bind_group = layout.len() as u32 - 2;
+        shader_defs.push(ShaderDefVal::UInt(
+            "GRID_VIEW_BIND_GROUP".into(),
+            first_grid_bind_group,
+        ));
+        shader_defs.push(ShaderDefVal::UInt(
+            "GRID_BIND_GROUP".into(),
+            first_grid_bind_group + 1,
+        ));
+
         RenderPipelineDescriptor {
             label: Some(Cow::Borrowed("grid-render-pipeline")),
-            layout: vec![self.view_layout.clone(), self.infinite_grid_layout.clone()],
+            layout,
             push_constant_ranges: Vec::new(),
             vertex: VertexState {
                 shader: GRID_SHADER_HANDLE,
-                shader_defs: vec![],
+                shader_defs: shader_defs.clone(),
                 entry_point: Some(Cow::Borrowed("vertex")),
                 buffers: vec![],
             },
@@ -524,8 +1820,12 @@ impl SpecializedRenderPipeline for InfiniteGridPipeline {
             },
             depth_stencil: Some(DepthStencilState {
                 format: TextureFormat::Depth32Float,
-                depth_write_enabled: false,
-                depth_compare: CompareFunction::Greater,
+                depth_write_enabled,
+                depth_compare: if depth_write_enabled {
+                    CompareFunction::GreaterEqual
+                } else {
+                    CompareFunction::Greater
+                },
                 stencil: StencilState {
                     front: StencilFaceState::IGNORE,
                     back: StencilFaceState::IGNORE,
@@ -545,15 +1845,229 @@ impl SpecializedRenderPipeline for InfiniteGridPipeline {
             },
             fragment: Some(FragmentState {
                 shader: GRID_SHADER_HANDLE,
-                shader_defs: vec![],
-                entry_point: Some(Cow::Borrowed("fragment")),
-                targets: vec![Some(ColorTargetState {
-                    format,
-                    blend: Some(BlendState::ALPHA_BLENDING),
-                    write_mask: ColorWrites::ALL,
-                })],
+                shader_defs,
+                entry_point: Some(Cow::Borrowed(if key.y_axis {
+                    "fragment_y_axis"
+                } else {
+                    "fragment"
+                })),
+                targets,
             }),
             zero_initialize_workgroup_memory: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_fades() {
+        let settings = InfiniteGridSettings {
+            fadeout_distance: 40.,
+            dot_fadeout_strength: 0.5,
+            ..default()
+        };
+        let uniform = GridDisplaySettingsUniform::from_settings(&settings);
+        assert_eq!(uniform.fade_kind, FADE_LINEAR);
+        assert_eq!(uniform.fade_params, Vec2::new(0., 40.));
+        assert_eq!(uniform.grazing_fade_kind, FADE_LINEAR);
+        assert_eq!(uniform.grazing_fade_params, Vec2::new(0.5, 1.));
+
+        // The defaults are the original min(1 - depth / 100, 1) and min(4 cos, 1)
+        let uniform = GridDisplaySettingsUniform::from_settings(&InfiniteGridSettings::default());
+        assert_eq!(uniform.fade_params, Vec2::new(0., 100.));
+        assert_eq!(uniform.grazing_fade_params, Vec2::new(0.75, 1.));
+
+        // The fades that replaced them win once they're set
+        let settings = InfiniteGridSettings {
+            fade: GridFade::Exponential { density: 0.1 },
+            grazing_fade: GridFade::None,
+            ..settings
+        };
+        let uniform = GridDisplaySettingsUniform::from_settings(&settings);
+        assert_eq!(uniform.fade_kind, FADE_EXPONENTIAL);
+        assert_eq!(uniform.fade_params, Vec2::new(0.1, 0.));
+        assert_eq!(uniform.grazing_fade_kind, FADE_NONE);
+    }
+
+    fn highlight(x: i32, y: i32, fill_color: Color) -> CellHighlight {
+        CellHighlight {
+            cell: IVec2::new(x, y),
+            fill_color,
+            outline_color: Color::NONE,
+        }
+    }
+
+    #[test]
+    fn cell_highlights_sorted_by_row() {
+        let highlights = [
+            highlight(1, 0, Color::WHITE),
+            highlight(-2, 1, Color::WHITE),
+            highlight(0, -1, Color::WHITE),
+            highlight(-3, 0, Color::WHITE),
+        ];
+        let cells: Vec<_> = sorted_cell_highlights(&highlights)
+            .iter()
+            .map(|highlight| highlight.cell)
+            .collect();
+        assert_eq!(
+            cells,
+            [
+                IVec2::new(0, -1),
+                IVec2::new(-3, 0),
+                IVec2::new(1, 0),
+                IVec2::new(-2, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn last_cell_highlight_wins() {
+        let red = Color::srgb(1., 0., 0.);
+        let green = Color::srgb(0., 1., 0.);
+        let blue = Color::srgb(0., 0., 1.);
+        let highlights = [
+            highlight(0, 0, red),
+            highlight(1, 0, red),
+            highlight(0, 0, green),
+            highlight(1, 0, blue),
+            highlight(0, 0, blue),
+        ];
+        let cells: Vec<_> = sorted_cell_highlights(&highlights)
+            .iter()
+            .map(|highlight| (highlight.cell, highlight.fill_color))
+            .collect();
+        assert_eq!(cells, [(IVec2::ZERO, blue), (IVec2::X, blue)]);
+    }
+
+    #[test]
+    fn fixed_periods_are_even() {
+        let periods = |major_line_every, super_major_line_every| {
+            let settings = InfiniteGridSettings {
+                major_line_every,
+                super_major_line_every,
+                ..default()
+            };
+            cell_periods(&settings, 10.).unwrap()
+        };
+        assert_eq!(periods(10, None), (DVec2::splat(10.), DVec2::splat(10.)));
+        assert_eq!(periods(5, None), (DVec2::splat(10.), DVec2::splat(10.)));
+        assert_eq!(periods(5, Some(3)), (DVec2::splat(30.), DVec2::splat(30.)));
+        assert_eq!(periods(4, Some(3)), (DVec2::splat(12.), DVec2::splat(12.)));
+        assert_eq!(periods(0, Some(0)), (DVec2::splat(2.), DVec2::splat(2.)));
+    }
+
+    #[test]
+    fn adaptive_periods_are_even() {
+        for base in [2, 3, 5, 10] {
+            let settings = InfiniteGridSettings {
+                subdivision: GridSubdivision::Adaptive { base },
+                ..default()
+            };
+            for view_distance_cells in [0., 0.5, 1., 7., 100., 1e4, 1e6] {
+                let (period, coarse_period) = cell_periods(&settings, view_distance_cells).unwrap();
+                assert_eq!(period.x, period.y);
+                assert_eq!(period.x % 2., 0., "base {base} at {view_distance_cells}");
+                assert_eq!(coarse_period.x % period.x, 0.);
+                assert!(period.x >= view_distance_cells * 1e3);
+                assert!(coarse_period.x >= period.x * 1e4);
+            }
+        }
+        let settings = InfiniteGridSettings {
+            subdivision: GridSubdivision::Adaptive { base: 10 },
+            ..default()
+        };
+        assert_eq!(
+            cell_periods(&settings, 42.).unwrap(),
+            (DVec2::splat(1e5), DVec2::splat(1e9))
+        );
+    }
+
+    #[test]
+    fn lattice_periods() {
+        let periods = |kind| {
+            let settings = InfiniteGridSettings { kind, ..default() };
+            cell_periods(&settings, 10.)
+        };
+        let sqrt_3 = 3f64.sqrt();
+        assert_eq!(
+            periods(GridKind::Polar {
+                ring_spacing: 1.,
+                spoke_count: 8
+            }),
+            None
+        );
+        assert_eq!(
+            periods(GridKind::Triangular),
+            Some((DVec2::new(1., sqrt_3), DVec2::new(1., sqrt_3)))
+        );
+        assert_eq!(
+            periods(GridKind::Hexagonal {
+                orientation: HexOrientation::FlatTop
+            }),
+            Some((DVec2::new(sqrt_3, 1.), DVec2::new(sqrt_3, 1.)))
+        );
+    }
+
+    #[test]
+    fn camera_phase_far_from_origin() {
+        let camera_cells = DVec2::new(6_543_210.25, -1_234_567.75);
+        let periods = Some((DVec2::splat(10.), DVec2::splat(1e4)));
+        assert_eq!(
+            CameraPhase::new(camera_cells, periods, true),
+            CameraPhase {
+                cell_phase: Vec2::new(0.25, 2.25),
+                coarse_cell_phase: Vec2::new(3210.25, 5432.25),
+                cell_period: 10.,
+                cell_shift: IVec2::new(6_543_210, -1_234_570),
+            }
+        );
+
+        // Only square cells are shifted
+        let phase = CameraPhase::new(camera_cells, periods, false);
+        assert_eq!(phase.cell_phase, Vec2::new(0.25, 2.25));
+        assert_eq!(phase.cell_shift, IVec2::ZERO);
+
+        // The shift wraps around past the range of i32
+        let phase = CameraPhase::new(DVec2::new(1e10 + 3.5, 0.), periods, true);
+        assert_eq!(phase.cell_phase, Vec2::new(3.5, 0.));
+        assert_eq!(phase.cell_shift.x, 1e10 as i64 as i32);
+
+        let phase = CameraPhase::new(camera_cells, None, true);
+        assert_eq!(phase.cell_phase, Vec2::ZERO);
+        assert_eq!(phase.cell_period, f32::MAX);
+        assert_eq!(phase.cell_shift, IVec2::ZERO);
+    }
+
+    #[test]
+    fn camera_phase_keeps_checkerboards_still() {
+        // The checkerboard alternates with the sum of the cell coordinates, so the whole cells
+        // left out of the phase must add up to an even count
+        for subdivision in [
+            GridSubdivision::Fixed,
+            GridSubdivision::Adaptive { base: 3 },
+            GridSubdivision::Adaptive { base: 5 },
+        ] {
+            let settings = InfiniteGridSettings {
+                subdivision,
+                major_line_every: 5,
+                super_major_line_every: Some(3),
+                ..default()
+            };
+            let periods = cell_periods(&settings, 20.);
+            for camera_cells in [
+                DVec2::new(1e6 + 1., 2e6 + 0.5),
+                DVec2::new(3_333_333.3, -7_777_777.7),
+                DVec2::new(-9_999_999.9, 1.),
+            ] {
+                let phase = CameraPhase::new(camera_cells, periods, true);
+                assert_eq!((phase.cell_shift.x + phase.cell_shift.y) % 2, 0);
+                let camera = phase.cell_shift.as_dvec2() + phase.cell_phase.as_dvec2();
+                assert!(camera.abs_diff_eq(camera_cells, 1e-2));
+            }
+        }
+    }
+}